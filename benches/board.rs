@@ -0,0 +1,225 @@
+/*
+ * Compares the row bitmask board of tetris::board with the map of cells it
+ * replaced, a Vec<Vec<u8>> tested cell by cell, on the boards of games played
+ * by the built-in AI, then times the searches built on the board. Run it with:
+ *
+ *     cargo bench --bench board
+ */
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+use tetris::ai::{self, Weights};
+use tetris::board::{Board, HEIGHT, WIDTH};
+use tetris::bot::BotGame;
+use tetris::engine::{State, TetriminoTypes};
+use tetris::movegen;
+use tetris::rules::{Mode, Rules};
+
+const PIECES: [TetriminoTypes; 7] = [
+    TetriminoTypes::TetriminoI,
+    TetriminoTypes::TetriminoJ,
+    TetriminoTypes::TetriminoL,
+    TetriminoTypes::TetriminoO,
+    TetriminoTypes::TetriminoS,
+    TetriminoTypes::TetriminoT,
+    TetriminoTypes::TetriminoZ,
+];
+const GAMES: u64 = 4;
+const PIECES_PER_GAME: u32 = 200;
+const ROUNDS: u32 = 20;
+
+type Map = Vec<Vec<u8>>;
+
+// A state of a piece as the 4x4 square of cells the engine tested.
+fn square(state: &State) -> Vec<Vec<u8>> {
+    let mut square = vec![vec![0; 4]; 4];
+    for (x, y) in state.cells {
+        square[y][x] = 1;
+    }
+    square
+}
+
+// Collisions and line clears of the map of cells, as the engine made them.
+fn map_test_position(map: &Map, square: &[Vec<u8>], x: isize, y: usize) -> bool {
+    for decal_y in 0..4 {
+        for decal_x in 0..4 {
+            let x = x + decal_x;
+            if square[decal_y][decal_x as usize] != 0
+                && (y + decal_y >= map.len()
+                    || x < 0
+                    || x as usize >= map[y + decal_y].len()
+                    || map[y + decal_y][x as usize] != 0)
+            {
+                return false;
+            }
+        }
+    }
+    true
+}
+
+fn map_clear_lines(map: &mut Map) -> u32 {
+    let rows = map.len();
+    map.retain(|line| line.contains(&0));
+    let cleared = rows - map.len();
+    for _ in 0..cleared {
+        map.insert(0, vec![0; WIDTH]);
+    }
+    cleared as u32
+}
+
+fn to_map(board: &Board) -> Map {
+    board.color_rows().iter().map(|row| row.to_vec()).collect()
+}
+
+// The boards left by every placement of games of the AI.
+fn boards() -> Vec<Board> {
+    let weights = Weights::default();
+    let mut boards = Vec::new();
+    for seed in 0..GAMES {
+        let mode = if seed % 2 == 0 {
+            Mode::Marathon(150)
+        } else {
+            Mode::Dig(10)
+        };
+        let mut game = BotGame::new(
+            Rules {
+                mode,
+                ..Rules::default()
+            },
+            seed,
+        );
+        while !game.is_over() && game.tetris.nb_pieces < PIECES_PER_GAME {
+            let best = ai::best_move(&game.tetris, &weights);
+            game.play_actions(&ai::move_actions(best));
+            boards.push(game.board().clone());
+        }
+    }
+    boards
+}
+
+// The boards with some random rows filled, for the line clears.
+fn full_rows(boards: &[Board]) -> Vec<Board> {
+    let mut rng = StdRng::seed_from_u64(0);
+    boards
+        .iter()
+        .map(|board| {
+            let mut board = board.clone();
+            for _ in 0..rng.gen_range(0..=4) {
+                let y = rng.gen_range(0..board.height());
+                board.set_row(y, &[1; WIDTH]);
+            }
+            board
+        })
+        .collect()
+}
+
+// Time taken by an operation, from the fastest of the rounds.
+fn time(operations: usize, mut f: impl FnMut() -> u64) -> Duration {
+    (0..ROUNDS)
+        .map(|_| {
+            let start = Instant::now();
+            black_box(f());
+            start.elapsed()
+        })
+        .min()
+        .unwrap_or_default()
+        / operations.max(1) as u32
+}
+
+fn compare(name: &str, map: Duration, board: Duration) {
+    println!(
+        "{:<14} {:>12.1?} {:>10.1?} {:>7.1}x",
+        name,
+        map,
+        board,
+        map.as_secs_f64() / board.as_secs_f64()
+    );
+}
+
+fn main() {
+    let boards = boards();
+    let maps = boards.iter().map(to_map).collect::<Vec<_>>();
+    let pieces = PIECES.map(|piece| piece.generate());
+    // Every position of every piece on every board, with the square of the
+    // state for the map of cells.
+    let mut positions = Vec::new();
+    for piece in &pieces {
+        for (state, cells) in piece.states().iter().enumerate() {
+            for x in -3..WIDTH as isize {
+                for y in 0..HEIGHT {
+                    positions.push((piece, state as u8, square(cells), x, y));
+                }
+            }
+        }
+    }
+    let tests = positions.len() * boards.len();
+    println!(
+        "{} boards, {} positions tested on each\n",
+        boards.len(),
+        positions.len()
+    );
+    println!(
+        "{:<14} {:>12} {:>10} {:>8}",
+        "", "Vec<Vec<u8>>", "Board", "speedup"
+    );
+
+    let map_collisions = time(tests, || {
+        let mut fits = 0;
+        for map in &maps {
+            for (_, _, square, x, y) in &positions {
+                fits += map_test_position(black_box(map), square, *x, *y) as u64;
+            }
+        }
+        fits
+    });
+    let board_collisions = time(tests, || {
+        let mut fits = 0;
+        for board in &boards {
+            for (piece, state, _, x, y) in &positions {
+                fits += piece.test_position(black_box(board), *state, *x, *y) as u64;
+            }
+        }
+        fits
+    });
+    compare("collision", map_collisions, board_collisions);
+
+    let filled = full_rows(&boards);
+    let filled_maps = filled.iter().map(to_map).collect::<Vec<_>>();
+    let map_clears = time(filled.len(), || {
+        let mut cleared = 0;
+        for map in &filled_maps {
+            cleared += map_clear_lines(&mut black_box(map).clone()) as u64;
+        }
+        cleared
+    });
+    let board_clears = time(filled.len(), || {
+        let mut cleared = 0;
+        for board in &filled {
+            cleared += black_box(board).clone().clear_lines().len() as u64;
+        }
+        cleared
+    });
+    compare("copy and clear", map_clears, board_clears);
+
+    println!();
+    let searches = time(boards.len() * pieces.len(), || {
+        let mut moves = 0;
+        for board in &boards {
+            for piece in &pieces {
+                moves += movegen::reachable_moves(black_box(board), piece).len() as u64;
+            }
+        }
+        moves
+    });
+    println!("{:<14} {:>12.1?} per piece", "movegen", searches);
+    let weights = Weights::default();
+    let evaluations = time(boards.len(), || {
+        let mut total = 0.0;
+        for board in &boards {
+            total += ai::evaluate(black_box(board), 0, &weights);
+        }
+        total as u64
+    });
+    println!("{:<14} {:>12.1?} per board", "evaluation", evaluations);
+}
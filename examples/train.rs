@@ -0,0 +1,120 @@
+/*
+ * Trains an agent on the reinforcement learning environment of tetris::env,
+ * without anything but the engine: a linear value function of the boards
+ * left by the placements, learnt by temporal difference as the agent plays.
+ * Every placement is looked ahead with Env::peek, and the agent plays the one
+ * with the best reward plus value of the board it leaves, or a random one
+ * while exploring. Run it with:
+ *
+ *     cargo run --release --example train -- <EPISODES>
+ */
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::env;
+use tetris::env::{ActionSpace, Env, EnvConfig, ObservationConfig, RewardConfig};
+
+const DISCOUNT: f64 = 0.95;
+const LEARNING_RATE: f64 = 0.01;
+// Chance of a random placement, going down from the first value to the last
+// over the episodes.
+const EXPLORATION: (f64, f64) = (0.1, 0.0);
+const MAX_PIECES: u32 = 500;
+
+// The value of an observation, the last weight being a bias.
+fn value(weights: &[f64], observation: &[f32]) -> f64 {
+    observation
+        .iter()
+        .zip(weights)
+        .map(|(x, w)| *x as f64 * w)
+        .sum::<f64>()
+        + weights[weights.len() - 1]
+}
+
+fn learn(weights: &mut [f64], observation: &[f32], error: f64) {
+    let bias = weights.len() - 1;
+    for (w, x) in weights.iter_mut().zip(observation) {
+        *w += LEARNING_RATE * error * *x as f64;
+    }
+    weights[bias] += LEARNING_RATE * error;
+}
+
+fn main() {
+    let episodes = env::args()
+        .nth(1)
+        .and_then(|arg| arg.parse::<u32>().ok())
+        .unwrap_or(30);
+    let mut env = Env::new(EnvConfig {
+        actions: ActionSpace::Placements,
+        observation: ObservationConfig {
+            board: false,
+            current_piece: false,
+            heights: true,
+            features: true,
+            piece: false,
+            queue: 0,
+            hold: false,
+        },
+        reward: RewardConfig {
+            holes: -0.5,
+            ..RewardConfig::default()
+        },
+        max_pieces: Some(MAX_PIECES),
+        ..EnvConfig::default()
+    });
+    let mut weights = vec![0.0; env.observation_size() + 1];
+    let mut rng = StdRng::seed_from_u64(0);
+
+    for episode in 0..episodes {
+        let progress = episode as f64 / episodes.max(2) as f64;
+        let exploration = EXPLORATION.0 + (EXPLORATION.1 - EXPLORATION.0) * progress;
+        env.reset(episode as u64);
+        // The board left by the last placement, which learns the value of
+        // the next one.
+        let mut last: Option<Vec<f32>> = None;
+        let mut total_reward = 0.0;
+        loop {
+            let candidates = (0..env.action_count())
+                .map(|action| {
+                    let (observation, reward, done, _) = env.peek(action);
+                    let future = if done {
+                        0.0
+                    } else {
+                        DISCOUNT * value(&weights, &observation)
+                    };
+                    (action, reward + future)
+                })
+                .collect::<Vec<_>>();
+            let chosen = if candidates.is_empty() {
+                (0, 0.0)
+            } else if rng.gen_bool(exploration) {
+                candidates[rng.gen_range(0..candidates.len())]
+            } else {
+                candidates
+                    .iter()
+                    .copied()
+                    .max_by(|a, b| a.1.total_cmp(&b.1))
+                    .unwrap_or((0, 0.0))
+            };
+            if let Some(ref last) = last {
+                let error = chosen.1 - value(&weights, last);
+                learn(&mut weights, last, error);
+            }
+
+            let (observation, reward, done, info) = env.step(chosen.0);
+            total_reward += reward;
+            if done {
+                println!(
+                    "episode {:>3}: {:>4} lines, {:>4} pieces, reward {:>8.2}{}",
+                    episode,
+                    info.lines,
+                    info.pieces,
+                    total_reward,
+                    if info.topped_out { ", topped out" } else { "" }
+                );
+                break;
+            }
+            last = Some(observation);
+        }
+    }
+    println!("weights: {:?}", weights);
+}
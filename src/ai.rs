@@ -0,0 +1,235 @@
+/*
+ * This module is the built-in AI player. It tries every position the current
+ * piece and the held one can lock in, as found by movegen, scores the board
+ * each of them leaves with a weighted sum of its features, and plays the best
+ * one a move at a time, through the same actions as a player. The weights are
+ * read from a plaintext file with one "<feature> <weight>" entry per line,
+ * the features being:
+ *
+ *     height      sum of the heights of the columns
+ *     holes       empty cells with a block above them
+ *     bumpiness   sum of the height differences between neighbour columns
+ *     wells       sum of the depths of the columns lower than both neighbours
+ *     lines       lines cleared by the placement
+ */
+use crate::board::Board;
+use crate::bot::{Agent, Plan};
+use crate::engine::{Action, Tetrimino, TetriminoTypes, Tetris};
+use crate::movegen::{self, Move};
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Weights {
+    pub height: f64,
+    pub holes: f64,
+    pub bumpiness: f64,
+    pub wells: f64,
+    pub lines: f64,
+}
+
+impl Default for Weights {
+    fn default() -> Weights {
+        Weights {
+            height: -0.51,
+            holes: -0.36,
+            bumpiness: -0.18,
+            wells: -0.05,
+            lines: 0.76,
+        }
+    }
+}
+
+impl Weights {
+    // Features missing from the text keep their default weight.
+    pub fn parse(content: &str) -> Result<Weights, String> {
+        let mut weights = Weights::default();
+        for line in content.lines() {
+            let words = line.split_whitespace().collect::<Vec<_>>();
+            let invalid = || format!("Invalid line: {}", line);
+            let (feature, value) = match words.as_slice() {
+                [] => continue,
+                [feature, value] => (*feature, value.parse::<f64>().map_err(|_| invalid())?),
+                _ => return Err(invalid()),
+            };
+            match feature {
+                "height" => weights.height = value,
+                "holes" => weights.holes = value,
+                "bumpiness" => weights.bumpiness = value,
+                "wells" => weights.wells = value,
+                "lines" => weights.lines = value,
+                _ => return Err(invalid()),
+            }
+        }
+        Ok(weights)
+    }
+
+    pub fn to_text(&self) -> String {
+        format!(
+            "height {}\nholes {}\nbumpiness {}\nwells {}\nlines {}\n",
+            self.height, self.holes, self.bumpiness, self.wells, self.lines
+        )
+    }
+
+    pub fn load(path: &Path) -> Result<Weights, String> {
+        let mut content = String::new();
+        File::open(path)
+            .and_then(|mut f| f.read_to_string(&mut content))
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        Weights::parse(&content).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+}
+
+// Locks the piece into a copy of the map and clears its full lines, returning
+// the new map and the number of lines cleared.
+pub fn lock_piece(game_map: &Board, piece: &Tetrimino) -> (Board, u32) {
+    let mut map = game_map.clone();
+    for (x, y) in piece.cells() {
+        map.set(x as usize, y, piece.kind.color());
+    }
+    let cleared = map.clear_lines().len();
+    (map, cleared as u32)
+}
+
+// The features of a board the evaluation is made of, but the lines cleared.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Features {
+    // Height of every column, from the bottom of the map to its highest block.
+    pub heights: Vec<usize>,
+    pub height: usize,
+    pub holes: usize,
+    pub bumpiness: usize,
+    pub wells: usize,
+}
+
+pub fn features(game_map: &Board) -> Features {
+    let rows = game_map.height();
+    let heights = (0..game_map.width())
+        .map(|x| {
+            game_map
+                .rows()
+                .iter()
+                .position(|row| row >> x & 1 != 0)
+                .map_or(0, |top| rows - top)
+        })
+        .collect::<Vec<_>>();
+    // The holes of a row are its free cells with a block above them.
+    let mut covered = 0;
+    let mut holes = 0;
+    for row in game_map.rows() {
+        holes += (covered & !row).count_ones() as usize;
+        covered |= row;
+    }
+    let bumpiness = heights
+        .windows(2)
+        .map(|pair| pair[0].abs_diff(pair[1]))
+        .sum::<usize>();
+    // The walls count as columns as high as the map.
+    let wells = (0..heights.len())
+        .map(|x| {
+            let left = if x == 0 { rows } else { heights[x - 1] };
+            let right = heights.get(x + 1).copied().unwrap_or(rows);
+            left.min(right).saturating_sub(heights[x])
+        })
+        .sum::<usize>();
+    Features {
+        height: heights.iter().sum(),
+        heights,
+        holes,
+        bumpiness,
+        wells,
+    }
+}
+
+// Scores a board left by a placement which cleared some lines, the higher the better.
+pub fn evaluate(game_map: &Board, cleared: u32, weights: &Weights) -> f64 {
+    let features = features(game_map);
+    weights.height * features.height as f64
+        + weights.holes * features.holes as f64
+        + weights.bumpiness * features.bumpiness as f64
+        + weights.wells * features.wells as f64
+        + weights.lines * cleared as f64
+}
+
+// The move of the current piece, or of the held one, leaving the best board,
+// and whether it is the held one. Ties go to the move needing the fewest
+// actions.
+pub fn best_move(tetris: &Tetris, weights: &Weights) -> Option<(bool, Move)> {
+    let current = tetris.current_piece?;
+    let held = tetris
+        .can_hold()
+        .then(|| tetris.hold_piece.or_else(|| tetris.queue.front().copied()))
+        .flatten()
+        .map(TetriminoTypes::generate);
+    let mut best: Option<(f64, bool, Move)> = None;
+    for (hold, piece) in [(false, Some(current)), (true, held)] {
+        let piece = match piece {
+            Some(piece) => piece,
+            None => continue,
+        };
+        for m in movegen::reachable_moves(&tetris.game_map, &piece) {
+            let (map, cleared) = lock_piece(&tetris.game_map, &m.piece(&piece));
+            let score = evaluate(&map, cleared, weights);
+            let better = match best {
+                Some((best_score, _, ref best_move)) => {
+                    score > best_score
+                        || (score == best_score && m.actions.len() < best_move.actions.len())
+                }
+                None => true,
+            };
+            if better {
+                best = Some((score, hold, m));
+            }
+        }
+    }
+    best.map(|(_, hold, m)| (hold, m))
+}
+
+// The actions of a move found by best_move, or a hard drop without one.
+pub fn move_actions(best: Option<(bool, Move)>) -> Vec<Action> {
+    match best {
+        Some((true, m)) => [Action::Hold].into_iter().chain(m.actions).collect(),
+        Some((false, m)) => m.actions,
+        None => vec![Action::HardDrop],
+    }
+}
+
+// Plays a game a move at a time, with a number of frames between two moves
+// so that it can be played against.
+pub struct Ai {
+    pub weights: Weights,
+    // The moves left to play for the current piece, planned when it spawned,
+    // and the number of the piece they are for.
+    plan: Plan,
+    planned_piece: Option<u32>,
+}
+
+impl Ai {
+    pub fn new(weights: Weights, move_delay: u32) -> Ai {
+        Ai {
+            weights,
+            plan: Plan::new(move_delay),
+            planned_piece: None,
+        }
+    }
+}
+
+impl Agent for Ai {
+    // The actions of the AI for the current frame of the game: its next move
+    // once the delay since the last one is over, or all of its moves at once
+    // without a delay.
+    fn actions(&mut self, tetris: &Tetris) -> Vec<Action> {
+        if tetris.current_piece.is_none() {
+            return Vec::new();
+        }
+        if self.planned_piece != Some(tetris.nb_pieces) {
+            self.planned_piece = Some(tetris.nb_pieces);
+            self.plan.set(
+                move_actions(best_move(tetris, &self.weights)),
+                tetris.frames,
+            );
+        }
+        self.plan.next(tetris)
+    }
+}
@@ -0,0 +1,130 @@
+/*
+ * This module contains the versus garbage rules, kept apart from the frontend
+ * so that anything driving a game can share them: the attack table turning
+ * line clears into garbage rows, the meter of garbage received and waiting to
+ * rise, which line clears cancel first, and the insertion of garbage rows at
+ * the bottom of a map.
+ */
+use crate::board::{Board, WIDTH};
+use crate::engine::GARBAGE;
+use rand::Rng;
+
+// Garbage rows sent by number of lines cleared at once, by a normal clear and
+// by a T-spin.
+const LINES_ATTACK: [u32; 5] = [0, 0, 1, 2, 4];
+const TSPIN_ATTACK: [u32; 4] = [0, 2, 4, 6];
+// Extra rows by number of line clears in a row, starting from the second one.
+const COMBO_ATTACK: [u32; 11] = [0, 1, 1, 2, 2, 3, 3, 4, 4, 4, 5];
+const BACK_TO_BACK_ATTACK: u32 = 1;
+const PERFECT_CLEAR_ATTACK: u32 = 10;
+
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub struct Attack {
+    // Line clears in a row, 0 when the last piece locked cleared nothing.
+    pub combo: u32,
+    // Whether the last line clear was a tetris or a T-spin, making the next
+    // one of those worth more.
+    pub back_to_back: bool,
+    // Garbage rows received and waiting to rise.
+    pub pending: u32,
+    // Garbage rows sent and not yet picked up by the opponent.
+    pub outgoing: u32,
+    // Garbage rows sent and cancelled since the start of the game.
+    pub sent: u32,
+    pub cancelled: u32,
+}
+
+// Garbage rows a line clear is worth, before cancelling the pending ones.
+pub fn lines_attack(
+    cleared: u32,
+    tspin: bool,
+    combo: u32,
+    back_to_back: bool,
+    perfect_clear: bool,
+) -> u32 {
+    if cleared == 0 {
+        return 0;
+    }
+    let mut attack = if tspin {
+        TSPIN_ATTACK[cleared.min(3) as usize]
+    } else {
+        LINES_ATTACK[cleared.min(4) as usize]
+    };
+    attack += COMBO_ATTACK[(combo.max(1) as usize - 1).min(COMBO_ATTACK.len() - 1)];
+    if back_to_back {
+        attack += BACK_TO_BACK_ATTACK;
+    }
+    if perfect_clear {
+        attack += PERFECT_CLEAR_ATTACK;
+    }
+    attack
+}
+
+impl Attack {
+    // Updates the combo and back-to-back after a piece locked, then uses the
+    // rows its line clear is worth to cancel the pending garbage first and
+    // sends the rest. Returns the number of rows sent.
+    pub fn lock(&mut self, cleared: u32, tspin: bool, perfect_clear: bool) -> u32 {
+        if cleared == 0 {
+            self.combo = 0;
+            return 0;
+        }
+        self.combo += 1;
+        let difficult = cleared >= 4 || tspin;
+        let mut attack = lines_attack(
+            cleared,
+            tspin,
+            self.combo,
+            difficult && self.back_to_back,
+            perfect_clear,
+        );
+        self.back_to_back = difficult;
+
+        let cancelled = attack.min(self.pending);
+        self.pending -= cancelled;
+        self.cancelled += cancelled;
+        attack -= cancelled;
+        self.outgoing += attack;
+        self.sent += attack;
+        attack
+    }
+
+    pub fn receive(&mut self, rows: u32) {
+        self.pending += rows;
+    }
+
+    // The garbage rows to send to the opponent.
+    pub fn take_outgoing(&mut self) -> u32 {
+        std::mem::take(&mut self.outgoing)
+    }
+
+    // The pending garbage rows, once they are due to rise.
+    pub fn take_pending(&mut self) -> u32 {
+        std::mem::take(&mut self.pending)
+    }
+}
+
+// Pushes garbage rows in from the bottom of the map, each with a single hole
+// at `hole`. The messiness is the chance in percent of the hole changing
+// column from one row to the next. Returns whether blocks were pushed out of
+// the top of the map.
+pub fn insert_garbage<R: Rng + ?Sized>(
+    game_map: &mut Board,
+    nb_rows: u32,
+    hole: &mut usize,
+    messiness: u32,
+    rng: &mut R,
+) -> bool {
+    let mut topped_out = false;
+    for _ in 0..nb_rows {
+        if rng.gen_range(0..100) < messiness {
+            *hole = rng.gen_range(0..WIDTH);
+        }
+        let mut row = [GARBAGE; WIDTH];
+        row[*hole] = 0;
+        if game_map.push_row(row) {
+            topped_out = true;
+        }
+    }
+    topped_out
+}
@@ -0,0 +1,186 @@
+/*
+ * This module plays many games with an agent without any frontend, spread
+ * over threads, and sums up their results, to compare bots and tune them.
+ * Every game is played from a seed of its own, so that the same seeds give
+ * the same games whatever the number of threads.
+ */
+use crate::bot::{Agent, BotGame};
+use crate::engine::{Tetris, FRAMES_PER_SECOND};
+use crate::rules::Rules;
+use serde::Serialize;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+#[derive(Serialize, Clone, Copy, PartialEq, Debug)]
+pub struct GameResult {
+    pub seed: u64,
+    pub score: u32,
+    pub lines: u32,
+    pub pieces: u32,
+    // Time the game lasted in the game itself, not the time taken to play it.
+    // Bots place a piece in the frame it spawns, so this is the time of the
+    // delays between pieces and after line clears, and the pieces per second
+    // would only tell the frame rate.
+    pub seconds: f64,
+    pub topped_out: bool,
+}
+
+impl GameResult {
+    pub fn of(tetris: &Tetris) -> GameResult {
+        GameResult {
+            seed: tetris.seed,
+            score: tetris.score,
+            lines: tetris.nb_lines,
+            pieces: tetris.nb_pieces,
+            seconds: tetris.frames as f64 / FRAMES_PER_SECOND as f64,
+            topped_out: tetris.game_over,
+        }
+    }
+}
+
+// Plays a game with the agent until it ends, or until it placed max_pieces.
+pub fn play_game(
+    agent: &mut dyn Agent,
+    rules: Rules,
+    seed: u64,
+    max_pieces: Option<u32>,
+) -> Tetris {
+    let mut game = BotGame::new(rules, seed);
+    while !game.is_over() && max_pieces.is_none_or(|max| game.tetris.nb_pieces < max) {
+        let actions = agent.actions(&game.tetris);
+        game.step(&actions);
+    }
+    game.tetris
+}
+
+// Plays a game from every seed on a number of threads, each one with an agent
+// of its own, and gives their results in the order of the seeds.
+pub fn run_games<F>(
+    rules: Rules,
+    seeds: &[u64],
+    max_pieces: Option<u32>,
+    threads: usize,
+    new_agent: F,
+) -> Result<Vec<GameResult>, String>
+where
+    F: Fn() -> Result<Box<dyn Agent>, String> + Sync,
+{
+    let next = AtomicUsize::new(0);
+    let results = Mutex::new(vec![None; seeds.len()]);
+    let errors = Mutex::new(Vec::new());
+    thread::scope(|scope| {
+        for _ in 0..threads.clamp(1, seeds.len().max(1)) {
+            scope.spawn(|| {
+                let mut agent = match new_agent() {
+                    Ok(agent) => agent,
+                    Err(e) => {
+                        errors.lock().unwrap().push(e);
+                        return;
+                    }
+                };
+                loop {
+                    let index = next.fetch_add(1, Ordering::Relaxed);
+                    let seed = match seeds.get(index) {
+                        Some(seed) => *seed,
+                        None => break,
+                    };
+                    let tetris = play_game(agent.as_mut(), rules, seed, max_pieces);
+                    results.lock().unwrap()[index] = Some(GameResult::of(&tetris));
+                }
+            });
+        }
+    });
+    if let Some(e) = errors.into_inner().unwrap().pop() {
+        return Err(e);
+    }
+    Ok(results
+        .into_inner()
+        .unwrap()
+        .into_iter()
+        .flatten()
+        .collect())
+}
+
+// How a value is spread over games, the percentiles being interpolated
+// between the two closest games.
+#[derive(Serialize, Clone, Copy, PartialEq, Debug, Default)]
+pub struct Stats {
+    pub mean: f64,
+    pub stddev: f64,
+    pub min: f64,
+    pub p10: f64,
+    pub p25: f64,
+    pub median: f64,
+    pub p75: f64,
+    pub p90: f64,
+    pub max: f64,
+}
+
+impl Stats {
+    pub fn of(values: &[f64]) -> Stats {
+        if values.is_empty() {
+            return Stats::default();
+        }
+        let mut sorted = values.to_vec();
+        sorted.sort_by(f64::total_cmp);
+        let n = sorted.len() as f64;
+        let mean = sorted.iter().sum::<f64>() / n;
+        let variance = sorted.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / n;
+        let percentile = |p: f64| {
+            let rank = p * (n - 1.0);
+            let (low, high) = (rank.floor() as usize, rank.ceil() as usize);
+            sorted[low] + (sorted[high] - sorted[low]) * (rank - low as f64)
+        };
+        Stats {
+            mean,
+            stddev: variance.sqrt(),
+            min: sorted[0],
+            p10: percentile(0.1),
+            p25: percentile(0.25),
+            median: percentile(0.5),
+            p75: percentile(0.75),
+            p90: percentile(0.9),
+            max: sorted[sorted.len() - 1],
+        }
+    }
+}
+
+#[derive(Serialize, Clone, Copy, PartialEq, Debug)]
+pub struct Summary {
+    pub games: usize,
+    pub lines: Stats,
+    pub score: Stats,
+    pub pieces: Stats,
+    pub seconds: Stats,
+    // 1 for the games which topped out and 0 for the others, the mean being
+    // the share of games lost.
+    pub topped_out: Stats,
+}
+
+impl Summary {
+    pub fn of(results: &[GameResult]) -> Summary {
+        let stats = |value: fn(&GameResult) -> f64| {
+            Stats::of(&results.iter().map(value).collect::<Vec<_>>())
+        };
+        Summary {
+            games: results.len(),
+            lines: stats(|result| result.lines as f64),
+            score: stats(|result| result.score as f64),
+            pieces: stats(|result| result.pieces as f64),
+            seconds: stats(|result| result.seconds),
+            topped_out: stats(|result| result.topped_out as u32 as f64),
+        }
+    }
+
+    // The statistics with the name of their value, in the order of the fields.
+    pub fn metrics(&self) -> [(&'static str, Stats); 5] {
+        [
+            ("lines", self.lines),
+            ("score", self.score),
+            ("pieces", self.pieces),
+            ("seconds", self.seconds),
+            ("topped_out", self.topped_out),
+        ]
+    }
+}
@@ -0,0 +1,219 @@
+/*
+ * Plays many games with the built-in AI or an external bot without any
+ * window, on every core at once, and prints statistics of their results as
+ * CSV or JSON, to compare bots and their settings. The games are played from
+ * consecutive seeds, so that two runs with the same seeds play the same
+ * pieces.
+ */
+use serde::Serialize;
+use std::env;
+use std::path::PathBuf;
+use std::process;
+use std::thread;
+use std::time::Instant;
+use tetris::ai::{Ai, Weights};
+use tetris::batch::{self, GameResult, Summary};
+use tetris::bot::Agent;
+use tetris::cli::{parse_preview, parse_value};
+use tetris::rules::{Mode, Rules};
+use tetris::tbp::TbpBot;
+
+const USAGE: &str = "Usage: tetris-batch [OPTIONS]
+
+Options:
+    --mode <MODE>                             Single player mode, as in replays
+                                              (default \"marathon 150\")
+    --preview <0-5>                           Number of next pieces shown to the bot (default 0)
+    --hold                                    Let the bot put the current piece aside
+    --games <N>                               Number of games (default 100)
+    --seed <N>                                Seed of the first game, the others following it
+                                              (default 0)
+    --max-pieces <N>                          Pieces after which a game is stopped
+    --threads <N>                             Games played at once (default one per core)
+    --weights <FILE>                          Weights of the built-in AI
+    --tbp <COMMAND>                           Play with an external bot instead, through the
+                                              Tetris Bot Protocol, one process per thread
+    --format <csv|json>                       Output format (default csv)
+    --games-results                           Print the result of every game too";
+
+const CSV_STATS_HEADER: &str = "metric,mean,stddev,min,p10,p25,median,p75,p90,max";
+const CSV_GAMES_HEADER: &str = "seed,score,lines,pieces,seconds,topped_out";
+
+#[derive(Clone, Copy, PartialEq)]
+enum Format {
+    Csv,
+    Json,
+}
+
+struct Config {
+    rules: Rules,
+    games: u64,
+    seed: u64,
+    max_pieces: Option<u32>,
+    threads: usize,
+    weights: Option<PathBuf>,
+    tbp: Option<Vec<String>>,
+    format: Format,
+    games_results: bool,
+}
+
+#[derive(Serialize)]
+struct Output<'a> {
+    summary: Summary,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    games: Option<&'a [GameResult]>,
+}
+
+fn parse_args() -> Result<Config, String> {
+    let mut config = Config {
+        rules: Rules {
+            mode: Mode::Marathon(150),
+            ..Rules::default()
+        },
+        games: 100,
+        seed: 0,
+        max_pieces: None,
+        threads: thread::available_parallelism().map_or(1, |n| n.get()),
+        weights: None,
+        tbp: None,
+        format: Format::Csv,
+        games_results: false,
+    };
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--mode" => {
+                config.rules.mode = parse_value(args.next(), &arg, |v| {
+                    Mode::from_words(&v.split_whitespace().collect::<Vec<_>>())
+                        .filter(|mode| mode.players() == 1)
+                })?
+            }
+            "--preview" => config.rules.preview = parse_value(args.next(), &arg, parse_preview)?,
+            "--hold" => config.rules.hold = true,
+            "--games" => config.games = parse_value(args.next(), &arg, |v| v.parse().ok())?,
+            "--seed" => config.seed = parse_value(args.next(), &arg, |v| v.parse().ok())?,
+            "--max-pieces" => {
+                config.max_pieces = Some(parse_value(args.next(), &arg, |v| v.parse().ok())?)
+            }
+            "--threads" => {
+                config.threads = parse_value(args.next(), &arg, |v| {
+                    v.parse().ok().filter(|threads| *threads > 0)
+                })?
+            }
+            "--weights" => {
+                config.weights = Some(parse_value(args.next(), &arg, |v| Some(PathBuf::from(v)))?)
+            }
+            "--tbp" => {
+                config.tbp = Some(parse_value(args.next(), &arg, |v| {
+                    let command = v.split_whitespace().map(str::to_string).collect::<Vec<_>>();
+                    (!command.is_empty()).then_some(command)
+                })?)
+            }
+            "--format" => {
+                config.format = parse_value(args.next(), &arg, |v| match v {
+                    "csv" => Some(Format::Csv),
+                    "json" => Some(Format::Json),
+                    _ => None,
+                })?
+            }
+            "--games-results" => config.games_results = true,
+            _ => return Err(format!("Unknown option: {}", arg)),
+        }
+    }
+    if config.weights.is_some() && config.tbp.is_some() {
+        return Err("--weights can't be used with --tbp".to_string());
+    }
+    Ok(config)
+}
+
+fn print_csv(summary: &Summary, results: &[GameResult], games_results: bool) {
+    println!("{}", CSV_STATS_HEADER);
+    for (metric, stats) in summary.metrics() {
+        println!(
+            "{},{:.4},{:.4},{:.4},{:.4},{:.4},{:.4},{:.4},{:.4},{:.4}",
+            metric,
+            stats.mean,
+            stats.stddev,
+            stats.min,
+            stats.p10,
+            stats.p25,
+            stats.median,
+            stats.p75,
+            stats.p90,
+            stats.max
+        );
+    }
+    if games_results {
+        println!();
+        println!("{}", CSV_GAMES_HEADER);
+        for result in results {
+            println!(
+                "{},{},{},{},{:.2},{}",
+                result.seed,
+                result.score,
+                result.lines,
+                result.pieces,
+                result.seconds,
+                result.topped_out as u32
+            );
+        }
+    }
+}
+
+fn main() {
+    let config = parse_args().unwrap_or_else(|e| {
+        eprintln!("{}\n\n{}", e, USAGE);
+        process::exit(1);
+    });
+    let weights = match config.weights {
+        Some(ref path) => Weights::load(path).unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            process::exit(1);
+        }),
+        None => Weights::default(),
+    };
+    let seeds = (config.seed..config.seed + config.games).collect::<Vec<_>>();
+
+    let start = Instant::now();
+    let results = batch::run_games(
+        config.rules,
+        &seeds,
+        config.max_pieces,
+        config.threads,
+        || match config.tbp {
+            Some(ref command) => {
+                TbpBot::launch(command, 0, true).map(|bot| Box::new(bot) as Box<dyn Agent>)
+            }
+            None => Ok(Box::new(Ai::new(weights, 0))),
+        },
+    )
+    .unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        process::exit(1);
+    });
+    let elapsed = start.elapsed().as_secs_f64();
+    eprintln!(
+        "{} games in {:.2} s ({:.1} games per second)",
+        results.len(),
+        elapsed,
+        results.len() as f64 / elapsed
+    );
+
+    let summary = Summary::of(&results);
+    match config.format {
+        Format::Csv => print_csv(&summary, &results, config.games_results),
+        Format::Json => {
+            let output = Output {
+                summary,
+                games: config.games_results.then_some(results.as_slice()),
+            };
+            match serde_json::to_string_pretty(&output) {
+                Ok(json) => println!("{}", json),
+                Err(e) => {
+                    eprintln!("Failed to write the statistics: {}", e);
+                    process::exit(1);
+                }
+            }
+        }
+    }
+}
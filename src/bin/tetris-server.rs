@@ -0,0 +1,203 @@
+/*
+ * The network versus play server: it hosts one room of two players at a time,
+ * starts their game once both are connected and relays what each of them
+ * sends to the other until one leaves. Spectators can join at any time on a
+ * port of their own and are sent the whole game so far, then every action.
+ * With --replay, the server hosts no players and streams a replay to its
+ * spectators instead, at the speed it was played, each time one joins.
+ */
+use std::env;
+use std::net::TcpListener;
+use std::path::PathBuf;
+use std::process;
+use std::sync::{Arc, Mutex};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+use tetris::net::{
+    Broadcast, Connection, Message, Stream, DEFAULT_DELAY, DEFAULT_PORT, DEFAULT_SPECTATOR_PORT,
+};
+use tetris::replay::Replay;
+use tetris::rules::{Finesse, Mode, Rules};
+
+const USAGE: &str = "Usage: tetris-server [OPTIONS]
+
+Options:
+    --port <N>                                Port to listen on (default 7777)
+    --spectator-port <N>                      Port to listen on for spectators (default 7778)
+    --delay <N>                               Input delay in frames (default 3)
+    --replay <FILE>                           Stream a replay to the spectators instead of
+                                              hosting games";
+
+const FRAMES_PER_SECOND: u32 = 60;
+
+struct Config {
+    port: u16,
+    spectator_port: u16,
+    delay: u32,
+    replay: Option<PathBuf>,
+}
+
+fn parse_args() -> Result<Config, String> {
+    let mut config = Config {
+        port: DEFAULT_PORT,
+        spectator_port: DEFAULT_SPECTATOR_PORT,
+        delay: DEFAULT_DELAY,
+        replay: None,
+    };
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let value = args.next();
+        let invalid = || format!("Invalid value for {}", arg);
+        match arg.as_str() {
+            "--port" => config.port = value.and_then(|v| v.parse().ok()).ok_or_else(invalid)?,
+            "--spectator-port" => {
+                config.spectator_port = value.and_then(|v| v.parse().ok()).ok_or_else(invalid)?
+            }
+            "--delay" => config.delay = value.and_then(|v| v.parse().ok()).ok_or_else(invalid)?,
+            "--replay" => config.replay = Some(value.map(PathBuf::from).ok_or_else(invalid)?),
+            _ => return Err(format!("Unknown option: {}", arg)),
+        }
+    }
+    Ok(config)
+}
+
+fn listen(port: u16) -> TcpListener {
+    TcpListener::bind(("0.0.0.0", port)).unwrap_or_else(|e| {
+        eprintln!("Failed to listen on port {}: {}", port, e);
+        process::exit(1);
+    })
+}
+
+fn accept_player(listener: &TcpListener, number: usize) -> Connection {
+    loop {
+        match listener.accept().and_then(|(stream, addr)| {
+            println!("Player {} joined from {}.", number, addr);
+            Connection::from_stream(stream)
+        }) {
+            Ok(connection) => return connection,
+            Err(e) => eprintln!("Failed to accept a player: {}", e),
+        }
+    }
+}
+
+// Streams the replay to the spectators each time one joins, from its start.
+fn stream_replay(replay: &Replay, broadcast: &Arc<Mutex<Broadcast>>) -> ! {
+    let last_frame = replay
+        .players
+        .iter()
+        .map(|player| player.outcome.frames)
+        .max()
+        .unwrap_or(0);
+    let inputs = replay
+        .players
+        .iter()
+        .map(|player| player.inputs.as_slice())
+        .collect::<Vec<_>>();
+    loop {
+        while broadcast.lock().unwrap().nb_spectators() == 0 {
+            sleep(Duration::from_millis(100));
+        }
+        println!("Streaming the replay.");
+        let seeds = replay.players.iter().map(|player| player.seed).collect();
+        // Replays never hold puzzles, the only games that can't be streamed.
+        let mut stream = Stream::start(Arc::clone(broadcast), replay.rules, seeds)
+            .expect("Failed to stream the replay.");
+        let start = Instant::now();
+        let frame = Duration::from_secs(1) / FRAMES_PER_SECOND;
+        for played in 0..last_frame {
+            stream.send_until(&inputs, played + 1);
+            sleep((start + frame * (played + 1)).saturating_duration_since(Instant::now()));
+        }
+        stream.end(&inputs, last_frame);
+        println!("Replay over, waiting for new spectators.");
+    }
+}
+
+// Relays the messages of each player to the other one and to the spectators
+// until one of them leaves, the other one being told by the caller.
+fn relay(players: &mut [Connection; 2], broadcast: &Mutex<Broadcast>) {
+    loop {
+        let mut idle = true;
+        for from in 0..2 {
+            while let Some(message) = players[from].try_recv() {
+                idle = false;
+                if message == Message::Quit || players[1 - from].send(&message).is_err() {
+                    return;
+                }
+                if let Message::Input { frame, actions } = message {
+                    broadcast.lock().unwrap().send(Message::Played {
+                        player: from,
+                        frame,
+                        actions,
+                    });
+                }
+            }
+        }
+        if idle {
+            sleep(Duration::from_millis(1));
+        }
+    }
+}
+
+fn main() {
+    let config = parse_args().unwrap_or_else(|e| {
+        eprintln!("{}\n\n{}", e, USAGE);
+        process::exit(1);
+    });
+    let broadcast = Broadcast::listen(config.spectator_port).unwrap_or_else(|e| {
+        eprintln!("Failed to listen on port {}: {}", config.spectator_port, e);
+        process::exit(1);
+    });
+    if let Some(ref path) = config.replay {
+        let replay = Replay::load(path).unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            process::exit(1);
+        });
+        println!(
+            "Streaming {} on port {} for spectators.",
+            path.display(),
+            config.spectator_port
+        );
+        stream_replay(&replay, &broadcast);
+    }
+    let listener = listen(config.port);
+    println!(
+        "Listening on port {}, and on port {} for spectators.",
+        config.port, config.spectator_port
+    );
+
+    loop {
+        let mut players = [accept_player(&listener, 1), accept_player(&listener, 2)];
+
+        let seed = rand::random();
+        let delay = config.delay;
+        let started = (0..2).all(|player| {
+            players[player]
+                .send(&Message::Start {
+                    player,
+                    seed,
+                    delay,
+                })
+                .is_ok()
+        });
+        if started {
+            println!("Game started.");
+            // The rules of network games, as played by the clients.
+            broadcast.lock().unwrap().send(Message::Game {
+                delay,
+                seeds: vec![seed, seed.wrapping_add(1)],
+                rules: Rules {
+                    mode: Mode::Versus,
+                    finesse: Finesse::Count,
+                    ..Rules::default()
+                },
+            });
+            relay(&mut players, &broadcast);
+        }
+        for player in players.iter_mut() {
+            let _ = player.send(&Message::Quit);
+        }
+        broadcast.lock().unwrap().end();
+        println!("Game over, waiting for new players.");
+    }
+}
@@ -0,0 +1,131 @@
+/*
+ * Plays games with an external bot through the Tetris Bot Protocol, without
+ * any window, and prints the result of every game. The games wait for the
+ * suggestions of the bot however long it thinks, so that a slow bot plays as
+ * well as a fast one, unless given a timeout. The games are played from seeds one after the other,
+ * and can be saved as replays, to be watched or checked with tetris-verify.
+ */
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::process;
+use std::time::Duration;
+use tetris::batch;
+use tetris::cli::{parse_preview, parse_value};
+use tetris::engine::FRAMES_PER_SECOND;
+use tetris::replay::Replay;
+use tetris::rules::{Mode, Rules};
+use tetris::tbp::TbpBot;
+
+const USAGE: &str = "Usage: tetris-tbp [OPTIONS] -- <BOT COMMAND>...
+
+Options:
+    --mode <MODE>                             Single player mode, as in replays
+                                              (default \"marathon 150\")
+    --preview <0-5>                           Number of next pieces shown to the bot (default 0)
+    --hold                                    Let the bot put the current piece aside
+    --seed <N>                                Seed of the first game (default 0)
+    --games <N>                               Number of games, with the following seeds
+                                              (default 1)
+    --max-pieces <N>                          Pieces after which a game is stopped
+    --timeout <SECONDS>                       Time the bot has to suggest a move, the built-in
+                                              AI playing in its place after it (default none)
+    --save <DIRECTORY>                        Save the games as replays in the directory";
+
+struct Config {
+    rules: Rules,
+    seed: u64,
+    games: u64,
+    max_pieces: Option<u32>,
+    timeout: Option<Duration>,
+    save: Option<PathBuf>,
+    command: Vec<String>,
+}
+
+fn parse_args() -> Result<Config, String> {
+    let mut config = Config {
+        rules: Rules {
+            mode: Mode::Marathon(150),
+            ..Rules::default()
+        },
+        seed: 0,
+        games: 1,
+        max_pieces: None,
+        timeout: None,
+        save: None,
+        command: Vec::new(),
+    };
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--mode" => {
+                config.rules.mode = parse_value(args.next(), &arg, |v| {
+                    Mode::from_words(&v.split_whitespace().collect::<Vec<_>>())
+                        .filter(|mode| mode.players() == 1)
+                })?
+            }
+            "--preview" => config.rules.preview = parse_value(args.next(), &arg, parse_preview)?,
+            "--hold" => config.rules.hold = true,
+            "--seed" => config.seed = parse_value(args.next(), &arg, |v| v.parse().ok())?,
+            "--games" => config.games = parse_value(args.next(), &arg, |v| v.parse().ok())?,
+            "--max-pieces" => {
+                config.max_pieces = Some(parse_value(args.next(), &arg, |v| v.parse().ok())?)
+            }
+            "--timeout" => {
+                config.timeout = Some(parse_value(args.next(), &arg, |v| {
+                    v.parse().ok().map(Duration::from_secs)
+                })?)
+            }
+            "--save" => {
+                config.save = Some(parse_value(args.next(), &arg, |v| Some(PathBuf::from(v)))?)
+            }
+            "--" => {
+                config.command = args.by_ref().collect();
+            }
+            _ => return Err(format!("Unknown option: {}", arg)),
+        }
+    }
+    if config.command.is_empty() {
+        return Err("No bot command given.".to_string());
+    }
+    Ok(config)
+}
+
+fn main() {
+    let config = parse_args().unwrap_or_else(|e| {
+        eprintln!("{}\n\n{}", e, USAGE);
+        process::exit(1);
+    });
+    let mut bot = TbpBot::launch(&config.command, 0, true).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        process::exit(1);
+    });
+    bot.suggestion_timeout = config.timeout;
+    println!("Bot: {} {} by {}", bot.name, bot.version, bot.author);
+
+    for seed in config.seed..config.seed + config.games {
+        let fallbacks = bot.fallbacks;
+        let tetris = &batch::play_game(&mut bot, config.rules, seed, config.max_pieces);
+        let seconds = tetris.frames as f64 / FRAMES_PER_SECOND as f64;
+        println!(
+            "seed {}: {} points, {} lines, {} pieces, {:.2} s, {} moves of the built-in AI{}",
+            seed,
+            tetris.score,
+            tetris.nb_lines,
+            tetris.nb_pieces,
+            seconds,
+            bot.fallbacks - fallbacks,
+            if tetris.game_over { ", topped out" } else { "" }
+        );
+        if let (Some(ref directory), Some(replay)) =
+            (&config.save, Replay::record(std::slice::from_ref(tetris)))
+        {
+            let path = directory.join(format!("tbp-{}.replay", seed));
+            if let Err(e) =
+                fs::create_dir_all(directory).and_then(|_| fs::write(&path, replay.to_text()))
+            {
+                eprintln!("Failed to save {}: {}", path.display(), e);
+            }
+        }
+    }
+}
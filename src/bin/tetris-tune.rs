@@ -0,0 +1,566 @@
+/*
+ * Tunes the weights of the built-in AI by playing games without any window.
+ * Every set of weights is scored by the mean of a result, such as the score,
+ * over games played from the same seeds, so that they are all compared on the
+ * same pieces. The weights evolve over generations, with either a genetic
+ * algorithm, keeping the best sets and breeding new ones from them, or hill
+ * climbing, trying variations of the best set found so far.
+ *
+ * The population is saved to a checkpoint file after every generation and
+ * the tuning goes on from it when started again with the same settings. A
+ * checkpoint looks like:
+ *
+ *     tetris-tune 1
+ *     method genetic
+ *     mode endless
+ *     preview 0
+ *     hold off
+ *     seeds 0 16
+ *     max-pieces 500
+ *     fitness score
+ *     generation 3
+ *     weights 2214.5 -0.51 -0.36 -0.18 -0.05 0.76
+ *
+ * with the fitness and the weights of every set of the population, in the
+ * order of the features of the weight files. The best weights found are also
+ * saved as a weight file, to be used with --ai-weights.
+ */
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::thread;
+use tetris::ai::{Ai, Weights};
+use tetris::batch::{self, GameResult};
+use tetris::bot::Agent;
+use tetris::cli::{parse_preview, parse_value};
+use tetris::rules::{Mode, Rules};
+
+const USAGE: &str = "Usage: tetris-tune [OPTIONS]
+
+Options:
+    --method <genetic|hill>                   Genetic algorithm or hill climbing (default genetic)
+    --generations <N>                         Generations to reach (default 20)
+    --population <N>                          Sets of weights by generation (default 16)
+    --mode <MODE>                             Single player mode, as in replays (default endless)
+    --preview <0-5>                           Number of next pieces shown to the AI (default 0)
+    --hold                                    Let the AI put the current piece aside
+    --games <N>                               Games played by every set of weights (default 16)
+    --seed <N>                                Seed of the first game, the others following it
+                                              (default 0)
+    --max-pieces <N>                          Pieces after which a game is stopped (default 500)
+    --fitness <score|lines|pieces>            Mean result the weights are scored by
+                                              (default score)
+    --threads <N>                             Games played at once (default one per core)
+    --checkpoint <FILE>                       Checkpoint to go on from and save the population to
+                                              (default tune.checkpoint)
+    --output <FILE>                           Weight file of the best weights (default best.weights)";
+
+const HEADER: &str = "tetris-tune 1";
+// Share of the population kept as it is by the genetic algorithm, sets taking
+// part in a tournament to be a parent, and chance and size of the mutation of
+// a weight.
+const ELITE_SHARE: f64 = 0.25;
+const TOURNAMENT_SIZE: usize = 3;
+const MUTATION_RATE: f64 = 0.3;
+const MUTATION_SIZE: f64 = 0.2;
+const NB_FEATURES: usize = 5;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Method {
+    Genetic,
+    Hill,
+}
+
+impl Method {
+    fn from_name(name: &str) -> Option<Method> {
+        match name {
+            "genetic" => Some(Method::Genetic),
+            "hill" => Some(Method::Hill),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Method::Genetic => "genetic",
+            Method::Hill => "hill",
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Fitness {
+    Score,
+    Lines,
+    Pieces,
+}
+
+impl Fitness {
+    fn from_name(name: &str) -> Option<Fitness> {
+        match name {
+            "score" => Some(Fitness::Score),
+            "lines" => Some(Fitness::Lines),
+            "pieces" => Some(Fitness::Pieces),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Fitness::Score => "score",
+            Fitness::Lines => "lines",
+            Fitness::Pieces => "pieces",
+        }
+    }
+
+    fn of(self, results: &[GameResult]) -> f64 {
+        let total = results
+            .iter()
+            .map(|result| match self {
+                Fitness::Score => result.score,
+                Fitness::Lines => result.lines,
+                Fitness::Pieces => result.pieces,
+            } as f64)
+            .sum::<f64>();
+        total / results.len().max(1) as f64
+    }
+}
+
+// The settings a checkpoint is only valid for, the fitness depending on them.
+#[derive(Clone, PartialEq, Debug)]
+struct Settings {
+    method: Method,
+    rules: Rules,
+    seed: u64,
+    games: u64,
+    max_pieces: u32,
+    fitness: Fitness,
+}
+
+struct Config {
+    settings: Settings,
+    generations: u32,
+    population: usize,
+    threads: usize,
+    checkpoint: PathBuf,
+    output: PathBuf,
+}
+
+// A set of weights and its fitness.
+#[derive(Clone, Copy, PartialEq, Debug)]
+struct Individual {
+    weights: [f64; NB_FEATURES],
+    fitness: f64,
+}
+
+struct Checkpoint {
+    settings: Settings,
+    generation: u32,
+    population: Vec<Individual>,
+}
+
+fn parse_mode(words: &str) -> Option<Mode> {
+    Mode::from_words(&words.split_whitespace().collect::<Vec<_>>())
+        .filter(|mode| mode.players() == 1)
+}
+
+fn parse_args() -> Result<Config, String> {
+    let mut config = Config {
+        settings: Settings {
+            method: Method::Genetic,
+            rules: Rules::default(),
+            seed: 0,
+            games: 16,
+            max_pieces: 500,
+            fitness: Fitness::Score,
+        },
+        generations: 20,
+        population: 16,
+        threads: thread::available_parallelism().map_or(1, |n| n.get()),
+        checkpoint: PathBuf::from("tune.checkpoint"),
+        output: PathBuf::from("best.weights"),
+    };
+    let settings = &mut config.settings;
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--method" => settings.method = parse_value(args.next(), &arg, Method::from_name)?,
+            "--generations" => {
+                config.generations = parse_value(args.next(), &arg, |v| v.parse().ok())?
+            }
+            "--population" => {
+                config.population = parse_value(args.next(), &arg, |v| {
+                    v.parse().ok().filter(|population| *population >= 2)
+                })?
+            }
+            "--mode" => settings.rules.mode = parse_value(args.next(), &arg, parse_mode)?,
+            "--preview" => settings.rules.preview = parse_value(args.next(), &arg, parse_preview)?,
+            "--hold" => settings.rules.hold = true,
+            "--games" => {
+                settings.games = parse_value(args.next(), &arg, |v| {
+                    v.parse().ok().filter(|games| *games > 0)
+                })?
+            }
+            "--seed" => settings.seed = parse_value(args.next(), &arg, |v| v.parse().ok())?,
+            "--max-pieces" => {
+                settings.max_pieces = parse_value(args.next(), &arg, |v| {
+                    v.parse().ok().filter(|pieces| *pieces > 0)
+                })?
+            }
+            "--fitness" => settings.fitness = parse_value(args.next(), &arg, Fitness::from_name)?,
+            "--threads" => {
+                config.threads = parse_value(args.next(), &arg, |v| {
+                    v.parse().ok().filter(|threads| *threads > 0)
+                })?
+            }
+            "--checkpoint" => {
+                config.checkpoint = parse_value(args.next(), &arg, |v| Some(PathBuf::from(v)))?
+            }
+            "--output" => {
+                config.output = parse_value(args.next(), &arg, |v| Some(PathBuf::from(v)))?
+            }
+            _ => return Err(format!("Unknown option: {}", arg)),
+        }
+    }
+    Ok(config)
+}
+
+fn to_array(weights: &Weights) -> [f64; NB_FEATURES] {
+    [
+        weights.height,
+        weights.holes,
+        weights.bumpiness,
+        weights.wells,
+        weights.lines,
+    ]
+}
+
+fn from_array(weights: [f64; NB_FEATURES]) -> Weights {
+    let [height, holes, bumpiness, wells, lines] = weights;
+    Weights {
+        height,
+        holes,
+        bumpiness,
+        wells,
+        lines,
+    }
+}
+
+// The evaluation only compares boards, so weights are kept at a length of 1,
+// two sets giving the same moves when one is a multiple of the other.
+fn normalize(mut weights: [f64; NB_FEATURES]) -> [f64; NB_FEATURES] {
+    let length = weights.iter().map(|w| w * w).sum::<f64>().sqrt();
+    if length > 0.0 {
+        for w in weights.iter_mut() {
+            *w /= length;
+        }
+    }
+    weights
+}
+
+impl Checkpoint {
+    fn to_text(&self) -> String {
+        let settings = &self.settings;
+        let mut text = format!(
+            "{}\nmethod {}\nmode {}\npreview {}\nhold {}\nseeds {} {}\nmax-pieces {}\nfitness {}\ngeneration {}\n",
+            HEADER,
+            settings.method.name(),
+            settings.rules.mode.to_words().unwrap_or_default(),
+            settings.rules.preview,
+            if settings.rules.hold { "on" } else { "off" },
+            settings.seed,
+            settings.games,
+            settings.max_pieces,
+            settings.fitness.name(),
+            self.generation
+        );
+        for individual in &self.population {
+            text += &format!("weights {}", individual.fitness);
+            for w in individual.weights {
+                text += &format!(" {}", w);
+            }
+            text += "\n";
+        }
+        text
+    }
+
+    fn parse(content: &str) -> Result<Checkpoint, String> {
+        let mut lines = content.lines();
+        if lines.next() != Some(HEADER) {
+            return Err("Not a checkpoint of this version of the tuner.".to_string());
+        }
+        let mut settings = Settings {
+            method: Method::Genetic,
+            rules: Rules::default(),
+            seed: 0,
+            games: 0,
+            max_pieces: 0,
+            fitness: Fitness::Score,
+        };
+        let mut generation = 0;
+        let mut population = Vec::new();
+        for line in lines {
+            let invalid = || format!("Invalid line: {}", line);
+            let words = line.split_whitespace().collect::<Vec<_>>();
+            match words.as_slice() {
+                [] => {}
+                ["method", name] => {
+                    settings.method = Method::from_name(name).ok_or_else(invalid)?
+                }
+                ["mode", mode @ ..] => {
+                    settings.rules.mode = Mode::from_words(mode).ok_or_else(invalid)?
+                }
+                ["preview", preview] => {
+                    settings.rules.preview = preview.parse().map_err(|_| invalid())?
+                }
+                ["hold", "on"] => settings.rules.hold = true,
+                ["hold", "off"] => settings.rules.hold = false,
+                ["seeds", seed, games] => {
+                    settings.seed = seed.parse().map_err(|_| invalid())?;
+                    settings.games = games.parse().map_err(|_| invalid())?;
+                }
+                ["max-pieces", pieces] => {
+                    settings.max_pieces = pieces.parse().map_err(|_| invalid())?
+                }
+                ["fitness", name] => {
+                    settings.fitness = Fitness::from_name(name).ok_or_else(invalid)?
+                }
+                ["generation", n] => generation = n.parse().map_err(|_| invalid())?,
+                ["weights", fitness, weights @ ..] if weights.len() == NB_FEATURES => {
+                    let mut individual = Individual {
+                        weights: [0.0; NB_FEATURES],
+                        fitness: fitness.parse().map_err(|_| invalid())?,
+                    };
+                    for (w, word) in individual.weights.iter_mut().zip(weights) {
+                        *w = word.parse().map_err(|_| invalid())?;
+                    }
+                    population.push(individual);
+                }
+                _ => return Err(invalid()),
+            }
+        }
+        if population.is_empty() {
+            return Err("No weights in the checkpoint.".to_string());
+        }
+        Ok(Checkpoint {
+            settings,
+            generation,
+            population,
+        })
+    }
+
+    fn load(path: &Path) -> Result<Checkpoint, String> {
+        let content = fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        Checkpoint::parse(&content).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    // Written next to the checkpoint first, so that a tuning stopped while
+    // saving still has its last checkpoint.
+    fn save(&self, path: &Path) -> Result<(), String> {
+        let temporary = path.with_extension("tmp");
+        fs::write(&temporary, self.to_text())
+            .and_then(|_| fs::rename(&temporary, path))
+            .map_err(|e| format!("Failed to save {}: {}", path.display(), e))
+    }
+}
+
+// The fitness of the weights over the games of the settings, or the error of
+// a game which couldn't be played.
+fn evaluate(
+    weights: [f64; NB_FEATURES],
+    settings: &Settings,
+    threads: usize,
+) -> Result<f64, String> {
+    let seeds = (settings.seed..settings.seed + settings.games).collect::<Vec<_>>();
+    let weights = from_array(weights);
+    let results = batch::run_games(
+        settings.rules,
+        &seeds,
+        Some(settings.max_pieces),
+        threads,
+        || Ok(Box::new(Ai::new(weights, 0)) as Box<dyn Agent>),
+    )
+    .map_err(|e| format!("Failed to evaluate the weights: {}", e))?;
+    Ok(settings.fitness.of(&results))
+}
+
+fn mutate(weights: [f64; NB_FEATURES], rng: &mut StdRng) -> [f64; NB_FEATURES] {
+    let mut mutated = weights;
+    // At least one weight changes, for the mutant to differ from its parent.
+    let forced = rng.gen_range(0..NB_FEATURES);
+    for (i, w) in mutated.iter_mut().enumerate() {
+        if i == forced || rng.gen_bool(MUTATION_RATE) {
+            *w += rng.gen_range(-MUTATION_SIZE..MUTATION_SIZE);
+        }
+    }
+    normalize(mutated)
+}
+
+// The best of a few sets drawn at random.
+fn tournament<'a>(population: &'a [Individual], rng: &mut StdRng) -> &'a Individual {
+    (0..TOURNAMENT_SIZE)
+        .map(|_| &population[rng.gen_range(0..population.len())])
+        .max_by(|a, b| a.fitness.total_cmp(&b.fitness))
+        .unwrap_or(&population[0])
+}
+
+// Weights between the ones of both parents, closer to the fitter one.
+fn crossover(a: &Individual, b: &Individual) -> [f64; NB_FEATURES] {
+    let (fa, fb) = (a.fitness.max(0.0), b.fitness.max(0.0));
+    let share = if fa + fb > 0.0 { fa / (fa + fb) } else { 0.5 };
+    let mut child = [0.0; NB_FEATURES];
+    for (i, w) in child.iter_mut().enumerate() {
+        *w = a.weights[i] * share + b.weights[i] * (1.0 - share);
+    }
+    normalize(child)
+}
+
+// The weights to evaluate for the next generation, from the population sorted
+// from the fittest: the elite and children of tournament winners for the
+// genetic algorithm, or variations of the best set for hill climbing.
+fn next_generation(
+    population: &[Individual],
+    method: Method,
+    size: usize,
+    rng: &mut StdRng,
+) -> Vec<[f64; NB_FEATURES]> {
+    match method {
+        Method::Genetic => {
+            let elite = ((size as f64 * ELITE_SHARE).ceil() as usize).min(population.len());
+            let mut weights = population[..elite]
+                .iter()
+                .map(|individual| individual.weights)
+                .collect::<Vec<_>>();
+            while weights.len() < size {
+                let a = tournament(population, rng);
+                let b = tournament(population, rng);
+                weights.push(mutate(crossover(a, b), rng));
+            }
+            weights
+        }
+        Method::Hill => {
+            let best = population[0].weights;
+            let mut weights = vec![best];
+            while weights.len() < size {
+                weights.push(mutate(best, rng));
+            }
+            weights
+        }
+    }
+}
+
+fn sort_population(population: &mut [Individual]) {
+    population.sort_by(|a, b| b.fitness.total_cmp(&a.fitness));
+}
+
+fn main() {
+    let config = parse_args().unwrap_or_else(|e| {
+        eprintln!("{}\n\n{}", e, USAGE);
+        process::exit(1);
+    });
+    let settings = &config.settings;
+    let exit = |e: String| -> ! {
+        eprintln!("{}", e);
+        process::exit(1);
+    };
+
+    let mut checkpoint = if config.checkpoint.exists() {
+        let checkpoint = Checkpoint::load(&config.checkpoint).unwrap_or_else(|e| exit(e));
+        if checkpoint.settings != *settings {
+            exit(format!(
+                "{} was made with other settings, give them again or use another checkpoint.",
+                config.checkpoint.display()
+            ));
+        }
+        println!(
+            "Going on from generation {} of {}",
+            checkpoint.generation,
+            config.checkpoint.display()
+        );
+        checkpoint
+    } else {
+        // The first generation starts from the default weights and variations
+        // of them.
+        let mut rng = StdRng::seed_from_u64(settings.seed);
+        let default = normalize(to_array(&Weights::default()));
+        let mut weights = vec![default];
+        while weights.len() < config.population {
+            weights.push(mutate(default, &mut rng));
+        }
+        let mut population = weights
+            .into_iter()
+            .map(|weights| {
+                Ok(Individual {
+                    weights,
+                    fitness: evaluate(weights, settings, config.threads)?,
+                })
+            })
+            .collect::<Result<Vec<_>, String>>()
+            .unwrap_or_else(|e| exit(e));
+        sort_population(&mut population);
+        Checkpoint {
+            settings: settings.clone(),
+            generation: 0,
+            population,
+        }
+    };
+
+    loop {
+        let best = checkpoint.population[0];
+        println!(
+            "generation {}: best {:.2}, mean {:.2}, weights {:?}",
+            checkpoint.generation,
+            best.fitness,
+            checkpoint
+                .population
+                .iter()
+                .map(|individual| individual.fitness)
+                .sum::<f64>()
+                / checkpoint.population.len() as f64,
+            best.weights
+        );
+        checkpoint
+            .save(&config.checkpoint)
+            .unwrap_or_else(|e| exit(e));
+        fs::write(&config.output, from_array(best.weights).to_text())
+            .unwrap_or_else(|e| exit(format!("Failed to save {}: {}", config.output.display(), e)));
+        if checkpoint.generation >= config.generations {
+            break;
+        }
+
+        // Every generation draws from a generator of its own, so that a
+        // tuning goes on the same way from a checkpoint.
+        checkpoint.generation += 1;
+        let mut rng = StdRng::seed_from_u64(settings.seed ^ checkpoint.generation as u64);
+        let weights = next_generation(
+            &checkpoint.population,
+            settings.method,
+            config.population,
+            &mut rng,
+        );
+        // The games being the same, the sets already evaluated keep their fitness.
+        let mut population = weights
+            .into_iter()
+            .map(|weights| {
+                let known = checkpoint
+                    .population
+                    .iter()
+                    .find(|individual| individual.weights == weights);
+                Ok(Individual {
+                    weights,
+                    fitness: match known {
+                        Some(individual) => individual.fitness,
+                        None => evaluate(weights, settings, config.threads)?,
+                    },
+                })
+            })
+            .collect::<Result<Vec<_>, String>>()
+            .unwrap_or_else(|e| exit(e));
+        sort_population(&mut population);
+        checkpoint.population = population;
+    }
+}
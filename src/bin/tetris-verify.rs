@@ -0,0 +1,250 @@
+/*
+ * The replay verifier: it plays replays again with the engine alone, without
+ * any window, and prints the result of every player along with whether it
+ * matches the result recorded in the replay. Directories are searched for
+ * replay files, so that a whole directory of games can be checked at once.
+ * It exits with an error if any replay failed to load or doesn't match.
+ */
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+use tetris::engine::{Tetris, FRAMES_PER_SECOND};
+use tetris::replay::{Outcome, Playback, Replay};
+
+const USAGE: &str = "Usage: tetris-verify [OPTIONS] <REPLAY OR DIRECTORY>...
+
+Options:
+    --csv                                     Print the results as CSV";
+
+const CSV_HEADER: &str =
+    "replay,mode,player,score,lines,level,seconds,pps,apm,finesse_faults,status";
+
+struct Config {
+    paths: Vec<PathBuf>,
+    csv: bool,
+}
+
+fn parse_args() -> Result<Config, String> {
+    let mut config = Config {
+        paths: Vec::new(),
+        csv: false,
+    };
+    for arg in env::args().skip(1) {
+        match arg.as_str() {
+            "--csv" => config.csv = true,
+            _ if arg.starts_with("--") => return Err(format!("Unknown option: {}", arg)),
+            _ => config.paths.push(PathBuf::from(arg)),
+        }
+    }
+    if config.paths.is_empty() {
+        return Err("No replay given.".to_string());
+    }
+    Ok(config)
+}
+
+// The replay files given, with the ones of the directories sorted by name.
+fn replay_files(paths: &[PathBuf]) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    for path in paths {
+        if !path.is_dir() {
+            files.push(path.clone());
+            continue;
+        }
+        let mut entries = fs::read_dir(path)
+            .map(|entries| {
+                entries
+                    .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                    .filter(|path| path.extension().is_some_and(|ext| ext == "replay"))
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_else(|e| {
+                eprintln!("Failed to read {}: {}", path.display(), e);
+                Vec::new()
+            });
+        entries.sort();
+        files.extend(entries);
+    }
+    files
+}
+
+struct PlayerResult {
+    outcome: Outcome,
+    seconds: f64,
+    pps: f64,
+    apm: f64,
+    finesse_faults: u32,
+    // The outcome recorded in the replay, if the game played again differs.
+    mismatch: Option<Outcome>,
+}
+
+impl PlayerResult {
+    fn new(tetris: &Tetris, recorded: Outcome) -> PlayerResult {
+        let outcome = Outcome::of(tetris);
+        let seconds = tetris.frames as f64 / FRAMES_PER_SECOND as f64;
+        let per_second = |count: u32| {
+            if seconds > 0.0 {
+                count as f64 / seconds
+            } else {
+                0.0
+            }
+        };
+        PlayerResult {
+            outcome,
+            seconds,
+            pps: per_second(tetris.nb_pieces),
+            apm: per_second(tetris.attack.sent) * 60.0,
+            finesse_faults: tetris.finesse_faults,
+            mismatch: (outcome != recorded).then_some(recorded),
+        }
+    }
+
+    fn status(&self) -> String {
+        match self.mismatch {
+            None => "valid".to_string(),
+            Some(recorded) => format!(
+                "MISMATCH (recorded {} points, {} lines, level {}, {} frames; got {} points, {} lines, level {}, {} frames)",
+                recorded.score,
+                recorded.lines,
+                recorded.level,
+                recorded.frames,
+                self.outcome.score,
+                self.outcome.lines,
+                self.outcome.level,
+                self.outcome.frames
+            ),
+        }
+    }
+}
+
+fn verify(replay: &Replay) -> Vec<PlayerResult> {
+    let mut playback = Playback::new(replay);
+    while !playback.is_finished() {
+        playback.step();
+    }
+    playback
+        .players()
+        .iter()
+        .zip(&replay.players)
+        .map(|(tetris, player)| PlayerResult::new(tetris, player.outcome))
+        .collect()
+}
+
+fn print_results(path: &Path, replay: &Replay, results: &[PlayerResult], csv: bool) {
+    let mode = replay.rules.mode.to_words().unwrap_or_default();
+    if !csv {
+        println!("{} ({})", path.display(), mode);
+    }
+    for (player, result) in results.iter().enumerate() {
+        let outcome = result.outcome;
+        if csv {
+            println!(
+                "{},{},{},{},{},{},{:.2},{:.2},{:.2},{},{}",
+                path.display(),
+                mode,
+                player + 1,
+                outcome.score,
+                outcome.lines,
+                outcome.level,
+                result.seconds,
+                result.pps,
+                result.apm,
+                result.finesse_faults,
+                if result.mismatch.is_none() {
+                    "valid"
+                } else {
+                    "mismatch"
+                }
+            );
+        } else {
+            println!(
+                "    player {}: {} points, {} lines, level {}, {:.2} s, {:.2} PPS, {:.2} APM, {} finesse faults: {}",
+                player + 1,
+                outcome.score,
+                outcome.lines,
+                outcome.level,
+                result.seconds,
+                result.pps,
+                result.apm,
+                result.finesse_faults,
+                result.status()
+            );
+        }
+    }
+}
+
+fn main() {
+    let config = parse_args().unwrap_or_else(|e| {
+        eprintln!("{}\n\n{}", e, USAGE);
+        process::exit(1);
+    });
+    if config.csv {
+        println!("{}", CSV_HEADER);
+    }
+    let mut nb_replays = 0;
+    let mut nb_invalid = 0;
+    for path in replay_files(&config.paths) {
+        nb_replays += 1;
+        let replay = match Replay::load(&path) {
+            Ok(replay) => replay,
+            Err(e) => {
+                eprintln!("{}", e);
+                nb_invalid += 1;
+                continue;
+            }
+        };
+        let results = verify(&replay);
+        if results.iter().any(|result| result.mismatch.is_some()) {
+            nb_invalid += 1;
+        }
+        print_results(&path, &replay, &results, config.csv);
+    }
+    if !config.csv {
+        println!(
+            "{} replays, {} valid, {} invalid",
+            nb_replays,
+            nb_replays - nb_invalid,
+            nb_invalid
+        );
+    }
+    if nb_invalid > 0 {
+        process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tetris::ai::{Ai, Weights};
+    use tetris::batch;
+    use tetris::rules::{Mode, Rules};
+
+    // A game of the AI, recorded as a replay.
+    fn recorded() -> Replay {
+        let rules = Rules {
+            mode: Mode::Sprint(40),
+            ..Rules::default()
+        };
+        let tetris = batch::play_game(&mut Ai::new(Weights::default(), 1), rules, 3, None);
+        Replay::record(&[tetris]).unwrap()
+    }
+
+    #[test]
+    fn recorded_game_is_valid() {
+        let results = verify(&recorded());
+        assert_eq!(results.len(), 1);
+        assert!(results[0].mismatch.is_none());
+        assert_eq!(results[0].status(), "valid");
+    }
+
+    #[test]
+    fn tampered_score_is_a_mismatch() {
+        let mut replay = recorded();
+        let recorded = replay.players[0].outcome;
+        replay.players[0].outcome.score += 100;
+        let results = verify(&replay);
+        assert_eq!(results[0].outcome, recorded);
+        assert_eq!(results[0].mismatch, Some(replay.players[0].outcome));
+        assert!(results[0].status().starts_with("MISMATCH"));
+    }
+}
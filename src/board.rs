@@ -0,0 +1,241 @@
+/*
+ * This module contains the board of a game. Every row is kept as a bitmask,
+ * bit x being set when the cell of column x is taken, so that a piece is
+ * tested against a row and full rows are found with a single operation, and
+ * the colour of every cell is kept apart, as the values of the pieces and
+ * GARBAGE, for drawing. Rows are numbered from the top, as the pieces' rows.
+ */
+
+pub const WIDTH: usize = 10;
+pub const HEIGHT: usize = 16;
+const FULL_ROW: u16 = (1 << WIDTH) - 1;
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Board {
+    rows: Vec<u16>,
+    colors: Vec<[u8; WIDTH]>,
+}
+
+impl Default for Board {
+    fn default() -> Board {
+        Board::new(HEIGHT)
+    }
+}
+
+impl Board {
+    pub fn new(height: usize) -> Board {
+        Board {
+            rows: vec![0; height],
+            colors: vec![[0; WIDTH]; height],
+        }
+    }
+
+    pub fn height(&self) -> usize {
+        self.rows.len()
+    }
+
+    pub fn width(&self) -> usize {
+        WIDTH
+    }
+
+    // The bitmasks of the rows, from the top.
+    pub fn rows(&self) -> &[u16] {
+        &self.rows
+    }
+
+    // The colours of the cells, row by row from the top.
+    pub fn color_rows(&self) -> &[[u8; WIDTH]] {
+        &self.colors
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> u8 {
+        self.colors[y][x]
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, color: u8) {
+        self.colors[y][x] = color;
+        if color == 0 {
+            self.rows[y] &= !(1 << x);
+        } else {
+            self.rows[y] |= 1 << x;
+        }
+    }
+
+    pub fn set_row(&mut self, y: usize, colors: &[u8]) {
+        for (x, color) in colors.iter().take(WIDTH).enumerate() {
+            self.set(x, y, *color);
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rows.iter().all(|row| *row == 0)
+    }
+
+    // Whether cells given as bitmasks of consecutive rows, shifted right by x
+    // columns and starting at row y, are all in the map and free.
+    pub fn fits(&self, masks: &[u16], x: isize, y: usize) -> bool {
+        for (decal_y, mask) in masks.iter().enumerate() {
+            if *mask == 0 {
+                continue;
+            }
+            let row = match self.rows.get(y + decal_y) {
+                Some(row) => *row,
+                None => return false,
+            };
+            let shifted = if x >= WIDTH as isize {
+                return false;
+            } else if x >= 0 {
+                (*mask as u32) << x
+            } else if *mask as u32 & ((1 << (-x).min(16)) - 1) != 0 {
+                // Cells out of the map on the left.
+                return false;
+            } else {
+                (*mask >> -x) as u32
+            };
+            if shifted & !(FULL_ROW as u32) != 0 || shifted as u16 & row != 0 {
+                return false;
+            }
+        }
+        true
+    }
+
+    // Removes the full rows, the rows above them going down, and gives the
+    // colours of the rows removed, from the top.
+    pub fn clear_lines(&mut self) -> Vec<[u8; WIDTH]> {
+        let mut cleared = Vec::new();
+        // Rows are moved down from the bottom up over the cleared ones.
+        let mut to = self.rows.len();
+        for from in (0..self.rows.len()).rev() {
+            if self.rows[from] == FULL_ROW {
+                cleared.push(self.colors[from]);
+                continue;
+            }
+            to -= 1;
+            self.rows[to] = self.rows[from];
+            self.colors[to] = self.colors[from];
+        }
+        for y in 0..to {
+            self.rows[y] = 0;
+            self.colors[y] = [0; WIDTH];
+        }
+        cleared.reverse();
+        cleared
+    }
+
+    // Pushes a row in from the bottom, the top row going out of the map.
+    // Returns whether the row pushed out had blocks.
+    pub fn push_row(&mut self, colors: [u8; WIDTH]) -> bool {
+        let topped_out = self.rows[0] != 0;
+        self.rows.remove(0);
+        self.colors.remove(0);
+        self.rows.push(
+            colors
+                .iter()
+                .enumerate()
+                .filter(|(_, color)| **color != 0)
+                .fold(0, |row, (x, _)| row | 1 << x),
+        );
+        self.colors.push(colors);
+        topped_out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FULL: [u8; WIDTH] = [1; WIDTH];
+
+    fn board(rows: &[[u8; WIDTH]]) -> Board {
+        let mut board = Board::new(rows.len());
+        for (y, colors) in rows.iter().enumerate() {
+            board.set_row(y, colors);
+        }
+        board
+    }
+
+    // The bitmask of every row matches the cells with a colour.
+    fn assert_in_step(board: &Board) {
+        for y in 0..board.height() {
+            for x in 0..WIDTH {
+                assert_eq!(board.rows()[y] & 1 << x != 0, board.get(x, y) != 0);
+            }
+        }
+    }
+
+    #[test]
+    fn clear_with_gaps_between_rows() {
+        let a = [2, 0, 2, 2, 2, 2, 2, 2, 2, 2];
+        let b = [0, 3, 3, 0, 0, 0, 0, 0, 0, 3];
+        let mut board = board(&[[0; WIDTH], a, [4; WIDTH], b, [5; WIDTH]]);
+        assert_eq!(board.clear_lines(), vec![[4; WIDTH], [5; WIDTH]]);
+        assert_eq!(
+            board.color_rows(),
+            &[[0; WIDTH], [0; WIDTH], [0; WIDTH], a, b]
+        );
+        assert_eq!(board.rows()[3], 0b11_1111_1101);
+        assert_eq!(board.rows()[4], 0b10_0000_0110);
+        assert_in_step(&board);
+    }
+
+    #[test]
+    fn clear_top_row() {
+        let a = [0, 6, 0, 0, 0, 0, 0, 0, 0, 0];
+        let mut board = board(&[FULL, [0; WIDTH], a]);
+        assert_eq!(board.clear_lines(), vec![FULL]);
+        assert_eq!(board.color_rows(), &[[0; WIDTH], [0; WIDTH], a]);
+        assert_eq!(board.rows(), &[0, 0, 0b10]);
+        assert_in_step(&board);
+    }
+
+    #[test]
+    fn clear_nothing() {
+        let a = [0, 6, 0, 0, 0, 0, 0, 0, 0, 7];
+        let mut board = board(&[[0; WIDTH], a]);
+        assert!(board.clear_lines().is_empty());
+        assert_eq!(board.color_rows(), &[[0; WIDTH], a]);
+    }
+
+    #[test]
+    fn fits_in_the_walls_and_floor() {
+        let board = Board::new(4);
+        // Two cells side by side, over two rows.
+        let masks = [0b11, 0b01];
+        assert!(board.fits(&masks, 0, 0));
+        assert!(board.fits(&masks, WIDTH as isize - 2, 2));
+        // Left wall.
+        assert!(!board.fits(&masks, -1, 0));
+        assert!(!board.fits(&masks, -20, 0));
+        // Empty columns of the masks may go out on the left.
+        assert!(board.fits(&[0b10, 0b10], -1, 0));
+        // Right wall.
+        assert!(!board.fits(&masks, WIDTH as isize - 1, 0));
+        assert!(!board.fits(&masks, WIDTH as isize, 0));
+        // Floor, empty rows of the masks may go below it.
+        assert!(!board.fits(&masks, 0, 3));
+        assert!(board.fits(&[0b11, 0], 0, 3));
+    }
+
+    #[test]
+    fn fits_against_the_stack() {
+        let mut board = Board::new(4);
+        board.set(4, 3, 1);
+        assert!(!board.fits(&[0b11], 3, 3));
+        assert!(!board.fits(&[0b11], 4, 3));
+        assert!(board.fits(&[0b11], 5, 3));
+        assert!(board.fits(&[0b11], 2, 3));
+        board.set(4, 3, 0);
+        assert!(board.fits(&[0b11], 3, 3));
+        assert!(board.is_empty());
+    }
+
+    #[test]
+    fn push_row_in_step() {
+        let mut board = board(&[[0; WIDTH], [0, 1, 0, 0, 0, 0, 0, 0, 0, 0]]);
+        let garbage = [8, 8, 8, 0, 8, 8, 8, 8, 8, 8];
+        assert!(!board.push_row(garbage));
+        assert_eq!(board.rows(), &[0b10, 0b11_1111_0111]);
+        assert!(board.push_row(garbage));
+        assert_in_step(&board);
+    }
+}
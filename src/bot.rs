@@ -0,0 +1,285 @@
+/*
+ * This module lets a program play a game instead of a player, without any
+ * frontend. The program reads the board, the current piece, the next pieces
+ * and the held one, as far as the rules have a preview and a hold, and
+ * either sends the actions of a frame as a player would or asks for a
+ * placement of the current piece, the game only going forward when it is
+ * told to. The actions of a placement are played in a single
+ * frame, so a game played by a bot is recorded and played back like any other.
+ */
+use crate::board::Board;
+use crate::engine::{apply_actions, Action, Tetrimino, TetriminoTypes, Tetris};
+use crate::movegen::{self, Move};
+use crate::rules::Rules;
+use std::collections::VecDeque;
+
+// Anything playing a game in place of a player, choosing its actions frame
+// by frame, as the built-in AI or an external bot.
+pub trait Agent {
+    fn actions(&mut self, tetris: &Tetris) -> Vec<Action>;
+}
+
+// The actions an agent planned for the current piece, given to the game one
+// at a time with a number of frames between two of them, so that it can be
+// played against, or all at once without a delay.
+pub struct Plan {
+    pub move_delay: u32,
+    actions: VecDeque<Action>,
+    // Frame of the next action.
+    next_move: u32,
+}
+
+impl Plan {
+    pub fn new(move_delay: u32) -> Plan {
+        Plan {
+            move_delay,
+            actions: VecDeque::new(),
+            next_move: 0,
+        }
+    }
+
+    // Replaces the plan, its first action waiting for the delay too.
+    pub fn set(&mut self, actions: impl IntoIterator<Item = Action>, frame: u32) {
+        self.actions = actions.into_iter().collect();
+        self.next_move = frame + self.move_delay;
+    }
+
+    // The actions of the plan for the current frame of the game.
+    pub fn next(&mut self, tetris: &Tetris) -> Vec<Action> {
+        if self.move_delay == 0 {
+            return self.actions.drain(..).collect();
+        }
+        if tetris.frames < self.next_move {
+            return Vec::new();
+        }
+        // The gravity may have brought the piece down already, and one soft
+        // drop too many would lock it.
+        let landed = tetris.current_piece.as_ref().is_some_and(|piece| {
+            !piece.test_position(&tetris.game_map, piece.rotation, piece.x, piece.y + 1)
+        });
+        while landed && self.actions.front() == Some(&Action::SoftDrop) {
+            self.actions.pop_front();
+        }
+        self.next_move = tetris.frames + self.move_delay;
+        self.actions.pop_front().into_iter().collect()
+    }
+}
+
+// Where to lock the current piece: the column and rotation state it ends in,
+// as in Tetrimino::x and Tetrimino::rotation, and whether its last move
+// is a rotation, to spin it into a spot it can't be dropped into.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Placement {
+    pub x: isize,
+    pub rotation: u8,
+    pub spin: bool,
+    // Whether to hold first and place the piece coming out of the hold instead.
+    pub hold: bool,
+}
+
+pub struct BotGame {
+    pub tetris: Tetris,
+}
+
+impl BotGame {
+    pub fn new(rules: Rules, seed: u64) -> BotGame {
+        BotGame::from_tetris(Tetris::with_seed(rules, seed))
+    }
+
+    pub fn from_tetris(tetris: Tetris) -> BotGame {
+        let mut game = BotGame { tetris };
+        game.wait_for_piece();
+        game
+    }
+
+    pub fn board(&self) -> &Board {
+        &self.tetris.game_map
+    }
+
+    pub fn current_piece(&self) -> Option<&Tetrimino> {
+        self.tetris.current_piece.as_ref()
+    }
+
+    pub fn current_type(&self) -> Option<TetriminoTypes> {
+        self.tetris
+            .current_piece
+            .as_ref()
+            .and(self.tetris.last_piece)
+    }
+
+    pub fn queue(&self) -> impl Iterator<Item = TetriminoTypes> + '_ {
+        self.tetris.queue.iter().copied()
+    }
+
+    pub fn hold_piece(&self) -> Option<TetriminoTypes> {
+        self.tetris.hold_piece
+    }
+
+    pub fn can_hold(&self) -> bool {
+        self.tetris.can_hold()
+    }
+
+    pub fn is_over(&self) -> bool {
+        self.tetris.game_over || self.tetris.is_complete()
+    }
+
+    // Plays a frame with the actions of the bot, as a player would.
+    pub fn step(&mut self, actions: &[Action]) {
+        if self.is_over() {
+            return;
+        }
+        apply_actions(&mut self.tetris, actions);
+        if !self.is_over() {
+            self.tetris.tick();
+        }
+    }
+
+    // Plays frames without any action until the next piece is there.
+    pub fn wait_for_piece(&mut self) {
+        while self.tetris.current_piece.is_none() && !self.is_over() {
+            self.step(&[]);
+        }
+    }
+
+    // The actions locking the current piece at the placement, or None if it
+    // can't get there.
+    pub fn placement_actions(&self, placement: Placement) -> Option<Vec<Action>> {
+        placement_path(&self.tetris, placement).map(|(actions, _)| actions)
+    }
+
+    // Every position the current piece, or the held one, can lock in, see
+    // movegen::reachable_moves. The actions of the moves of the held piece
+    // start with the hold.
+    pub fn moves(&self, hold: bool) -> Vec<Move> {
+        let piece = if hold {
+            held_piece(&self.tetris)
+        } else {
+            self.tetris.current_piece
+        };
+        let mut moves = piece
+            .map(|piece| movegen::reachable_moves(&self.tetris.game_map, &piece))
+            .unwrap_or_default();
+        if hold {
+            for m in moves.iter_mut() {
+                m.actions.insert(0, Action::Hold);
+            }
+        }
+        moves
+    }
+
+    // Plays the actions of a move, or any actions locking the current piece,
+    // in a frame and plays on until the next piece is there.
+    pub fn play_actions(&mut self, actions: &[Action]) {
+        self.step(actions);
+        self.wait_for_piece();
+    }
+
+    // Locks the current piece at the placement and plays on until the next
+    // piece is there. Returns false, without playing, if the current piece
+    // can't get there.
+    pub fn play(&mut self, placement: Placement) -> bool {
+        let actions = match self.placement_actions(placement) {
+            Some(actions) => actions,
+            None => return false,
+        };
+        self.play_actions(&actions);
+        true
+    }
+}
+
+// The piece the current one would be swapped with by a hold.
+fn held_piece(tetris: &Tetris) -> Option<Tetrimino> {
+    if !tetris.can_hold() {
+        return None;
+    }
+    tetris
+        .hold_piece
+        .or_else(|| tetris.queue.front().copied())
+        .map(TetriminoTypes::generate)
+}
+
+// The actions locking the current piece of a game at the placement and the
+// piece where it locks, or None if it can't get there.
+pub fn placement_path(tetris: &Tetris, placement: Placement) -> Option<(Vec<Action>, Tetrimino)> {
+    let piece = if placement.hold {
+        held_piece(tetris)?
+    } else {
+        tetris.current_piece?
+    };
+    let (mut actions, piece) = find_path(&tetris.game_map, &piece, placement)?;
+    if placement.hold {
+        actions.insert(0, Action::Hold);
+    }
+    Some((actions, piece))
+}
+
+fn shift_actions(piece: &mut Tetrimino, game_map: &Board, x: isize) -> Option<Vec<Action>> {
+    let mut actions = Vec::new();
+    while piece.x != x {
+        let (new_x, action) = if x < piece.x {
+            (piece.x - 1, Action::MoveLeft)
+        } else {
+            (piece.x + 1, Action::MoveRight)
+        };
+        if !piece.change_position(game_map, new_x, piece.y) {
+            return None;
+        }
+        actions.push(action);
+    }
+    Some(actions)
+}
+
+fn rotate_actions(piece: &mut Tetrimino, game_map: &Board, rotation: u8) -> Option<Vec<Action>> {
+    let mut actions = Vec::new();
+    while piece.rotation != rotation {
+        if !piece.rotate(game_map) {
+            return None;
+        }
+        actions.push(Action::Rotate);
+    }
+    Some(actions)
+}
+
+// Rotates the piece, moves it to the column and drops it, or for a spin drops
+// it from the previous rotation state and rotates it at the bottom, trying
+// every column it can start from since the rotation can push it aside.
+fn find_path(
+    game_map: &Board,
+    piece: &Tetrimino,
+    placement: Placement,
+) -> Option<(Vec<Action>, Tetrimino)> {
+    let nb_states = piece.states().len() as u8;
+    if placement.rotation >= nb_states {
+        return None;
+    }
+    if !placement.spin {
+        let mut piece = *piece;
+        let mut actions = rotate_actions(&mut piece, game_map, placement.rotation)?;
+        actions.extend(shift_actions(&mut piece, game_map, placement.x)?);
+        while piece.change_position(game_map, piece.x, piece.y + 1) {}
+        actions.push(Action::HardDrop);
+        return Some((actions, piece));
+    }
+
+    let before = (placement.rotation + nb_states - 1) % nb_states;
+    (-3..10)
+        .filter_map(|start_x| {
+            let mut piece = *piece;
+            let mut actions = rotate_actions(&mut piece, game_map, before)?;
+            actions.extend(shift_actions(&mut piece, game_map, start_x)?);
+            while piece.change_position(game_map, piece.x, piece.y + 1) {
+                actions.push(Action::SoftDrop);
+            }
+            if nb_states == 1 || !piece.rotate(game_map) {
+                return None;
+            }
+            let landed = !piece.test_position(game_map, piece.rotation, piece.x, piece.y + 1);
+            if piece.x != placement.x || !landed {
+                return None;
+            }
+            actions.push(Action::Rotate);
+            actions.push(Action::HardDrop);
+            Some((actions, piece))
+        })
+        .min_by_key(|(actions, _)| actions.len())
+}
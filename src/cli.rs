@@ -0,0 +1,25 @@
+/*
+ * This module parses the values of the command line options shared by the
+ * game and the tools, each tool reading its own list of options.
+ */
+use crate::engine::PREVIEW_PIECES;
+
+// The value following an option, parsed, or an error naming the option.
+pub fn parse_value<T>(
+    value: Option<String>,
+    flag: &str,
+    parse: impl Fn(&str) -> Option<T>,
+) -> Result<T, String> {
+    match value {
+        Some(value) => parse(&value).ok_or(format!("Invalid value for {}: {}", flag, value)),
+        None => Err(format!("Missing value for {}", flag)),
+    }
+}
+
+// Number of pieces to come shown, up to the ones the engine keeps.
+pub fn parse_preview(value: &str) -> Option<usize> {
+    value
+        .parse::<usize>()
+        .ok()
+        .filter(|preview| *preview <= PREVIEW_PIECES)
+}
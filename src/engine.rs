@@ -0,0 +1,940 @@
+/*
+ * This module contains the game engine, without any frontend: the pieces, the
+ * board and the rules of a game, played one frame at a time with the actions
+ * of the player.
+ */
+use crate::attack::{self, Attack};
+use crate::board::Board;
+use crate::movegen;
+use crate::puzzle::{Goal, Puzzle};
+use crate::rules::{self, Finesse, Mode, Rules};
+use rand::{
+    distributions::{Distribution, Standard},
+    rngs::StdRng,
+    Rng, SeedableRng,
+};
+use std::collections::VecDeque;
+
+pub const FRAMES_PER_SECOND: u32 = 60;
+// Value of the garbage cells in the game map, the pieces using 1 to 7.
+pub const GARBAGE: u8 = 8;
+// Garbage rows on the board at the start of a dig, and seconds between new ones.
+const DIG_ROWS: u32 = 8;
+const DIG_INTERVAL: u32 = 5;
+// Most pieces to come the preview can show.
+pub const PREVIEW_PIECES: usize = 5;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Action {
+    MoveLeft,
+    MoveRight,
+    SoftDrop,
+    HardDrop,
+    Rotate,
+    Hold,
+    Confirm,
+    Quit,
+}
+
+impl Action {
+    pub fn from_name(name: &str) -> Option<Action> {
+        match name {
+            "left" => Some(Action::MoveLeft),
+            "right" => Some(Action::MoveRight),
+            "down" => Some(Action::SoftDrop),
+            "drop" => Some(Action::HardDrop),
+            "rotate" => Some(Action::Rotate),
+            "hold" => Some(Action::Hold),
+            "confirm" => Some(Action::Confirm),
+            "quit" => Some(Action::Quit),
+            _ => None,
+        }
+    }
+
+    // Single character names of the game actions, used in network messages and replays.
+    pub fn to_char(self) -> Option<char> {
+        match self {
+            Action::MoveLeft => Some('L'),
+            Action::MoveRight => Some('R'),
+            Action::SoftDrop => Some('D'),
+            Action::HardDrop => Some('H'),
+            Action::Rotate => Some('U'),
+            Action::Hold => Some('C'),
+            Action::Confirm | Action::Quit => None,
+        }
+    }
+
+    pub fn from_char(c: char) -> Option<Action> {
+        match c {
+            'L' => Some(Action::MoveLeft),
+            'R' => Some(Action::MoveRight),
+            'D' => Some(Action::SoftDrop),
+            'H' => Some(Action::HardDrop),
+            'U' => Some(Action::Rotate),
+            'C' => Some(Action::Hold),
+            _ => None,
+        }
+    }
+}
+
+// A rotation state of a piece: the cells it covers, as columns and rows from
+// the top left corner of the 4x4 square it fits in, and the same cells as a
+// bitmask by row, bit x for column x, to test them against the board.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct State {
+    pub cells: [(usize, usize); 4],
+    pub masks: [u16; 4],
+}
+
+const fn state(cells: [(usize, usize); 4]) -> State {
+    let mut masks = [0; 4];
+    let mut i = 0;
+    while i < cells.len() {
+        masks[cells[i].1] |= 1 << cells[i].0;
+        i += 1;
+    }
+    State { cells, masks }
+}
+
+// The rotation states of every piece, in the order of TetriminoTypes, a
+// rotation going to the next one.
+const STATES: [&[State]; 7] = [
+    // I
+    &[
+        state([(0, 0), (1, 0), (2, 0), (3, 0)]),
+        state([(1, 0), (1, 1), (1, 2), (1, 3)]),
+    ],
+    // J
+    &[
+        state([(0, 0), (1, 0), (2, 0), (2, 1)]),
+        state([(0, 0), (1, 0), (0, 1), (0, 2)]),
+        state([(0, 0), (0, 1), (1, 1), (2, 1)]),
+        state([(1, 0), (1, 1), (0, 2), (1, 2)]),
+    ],
+    // L
+    &[
+        state([(0, 0), (1, 0), (2, 0), (0, 1)]),
+        state([(0, 0), (1, 0), (1, 1), (1, 2)]),
+        state([(2, 0), (0, 1), (1, 1), (2, 1)]),
+        state([(0, 0), (0, 1), (0, 2), (1, 2)]),
+    ],
+    // O
+    &[state([(0, 0), (1, 0), (0, 1), (1, 1)])],
+    // S
+    &[
+        state([(1, 0), (2, 0), (0, 1), (1, 1)]),
+        state([(1, 0), (1, 1), (2, 1), (2, 2)]),
+    ],
+    // T
+    &[
+        state([(0, 0), (1, 0), (2, 0), (1, 1)]),
+        state([(1, 0), (0, 1), (1, 1), (1, 2)]),
+        state([(1, 0), (0, 1), (1, 1), (2, 1)]),
+        state([(1, 0), (1, 1), (2, 1), (1, 2)]),
+    ],
+    // Z
+    &[
+        state([(0, 0), (1, 0), (1, 1), (2, 1)]),
+        state([(2, 0), (1, 1), (2, 1), (1, 2)]),
+    ],
+];
+
+// A piece in the game, its position being the top left corner of the square
+// its states fit in, which can stick out of the map on the left.
+#[derive(Clone, Copy, PartialEq)]
+pub struct Tetrimino {
+    pub kind: TetriminoTypes,
+    pub x: isize,
+    pub y: usize,
+    pub rotation: u8,
+}
+
+impl Tetrimino {
+    pub fn states(&self) -> &'static [State] {
+        self.kind.states()
+    }
+
+    // The cells the piece covers, as columns and rows of the map.
+    pub fn cells(&self) -> [(isize, usize); 4] {
+        self.states()[self.rotation as usize]
+            .cells
+            .map(|(x, y)| (self.x + x as isize, self.y + y))
+    }
+
+    pub fn rotate(&mut self, game_map: &Board) -> bool {
+        match self.rotated_position(game_map, self.rotation, self.x, self.y) {
+            Some((state, x)) => {
+                self.rotation = state;
+                self.x = x;
+                true
+            }
+            None => false,
+        }
+    }
+
+    // The rotation state and column a rotation from the given position ends
+    // in, the piece being pushed aside when it doesn't fit where it is.
+    pub fn rotated_position(
+        &self,
+        game_map: &Board,
+        state: u8,
+        x: isize,
+        y: usize,
+    ) -> Option<(u8, isize)> {
+        let mut tmp_state = state + 1;
+        if tmp_state >= self.states().len() as u8 {
+            tmp_state = 0;
+        }
+        let x_pos = [0, -1, 1, -2, 2, -3];
+        x_pos
+            .iter()
+            .find(|decal_x| self.test_position(game_map, tmp_state, x + *decal_x, y))
+            .map(|decal_x| (tmp_state, x + decal_x))
+    }
+
+    pub fn test_position(&self, game_map: &Board, tmp_state: u8, x: isize, y: usize) -> bool {
+        game_map.fits(&self.states()[tmp_state as usize].masks, x, y)
+    }
+
+    pub fn test_current_position(&self, game_map: &Board) -> bool {
+        self.test_position(game_map, self.rotation, self.x, self.y)
+    }
+
+    pub fn change_position(&mut self, game_map: &Board, new_x: isize, new_y: usize) -> bool {
+        if self.test_position(game_map, self.rotation, new_x, new_y) {
+            self.x = new_x;
+            self.y = new_y;
+            return true;
+        }
+        false
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum TetriminoTypes {
+    TetriminoI,
+    TetriminoJ,
+    TetriminoL,
+    TetriminoO,
+    TetriminoS,
+    TetriminoT,
+    TetriminoZ,
+}
+
+impl Distribution<TetriminoTypes> for Standard {
+    fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> TetriminoTypes {
+        match rng.gen_range(0..=6) {
+            0 => TetriminoTypes::TetriminoI,
+            1 => TetriminoTypes::TetriminoJ,
+            2 => TetriminoTypes::TetriminoL,
+            3 => TetriminoTypes::TetriminoO,
+            4 => TetriminoTypes::TetriminoS,
+            5 => TetriminoTypes::TetriminoT,
+            _ => TetriminoTypes::TetriminoZ,
+        }
+    }
+}
+
+impl TetriminoTypes {
+    pub fn from_char(c: char) -> Option<TetriminoTypes> {
+        match c {
+            'I' => Some(TetriminoTypes::TetriminoI),
+            'J' => Some(TetriminoTypes::TetriminoJ),
+            'L' => Some(TetriminoTypes::TetriminoL),
+            'O' => Some(TetriminoTypes::TetriminoO),
+            'S' => Some(TetriminoTypes::TetriminoS),
+            'T' => Some(TetriminoTypes::TetriminoT),
+            'Z' => Some(TetriminoTypes::TetriminoZ),
+            _ => None,
+        }
+    }
+
+    pub fn to_char(self) -> char {
+        match self {
+            TetriminoTypes::TetriminoI => 'I',
+            TetriminoTypes::TetriminoJ => 'J',
+            TetriminoTypes::TetriminoL => 'L',
+            TetriminoTypes::TetriminoO => 'O',
+            TetriminoTypes::TetriminoS => 'S',
+            TetriminoTypes::TetriminoT => 'T',
+            TetriminoTypes::TetriminoZ => 'Z',
+        }
+    }
+
+    pub fn states(self) -> &'static [State] {
+        STATES[self as usize]
+    }
+
+    // The value of the cells of the piece in the map.
+    pub fn color(self) -> u8 {
+        self as u8 + 1
+    }
+
+    // The piece as it spawns at the top of the map.
+    pub fn generate(self) -> Tetrimino {
+        Tetrimino {
+            kind: self,
+            x: if self == Self::TetriminoO { 5 } else { 4 },
+            y: 0,
+            rotation: 0,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Tetris {
+    pub game_map: Board,
+    pub rules: Rules,
+    pub current_level: u32,
+    pub score: u32,
+    pub nb_lines: u32,
+    pub level_lines: u32,
+    pub nb_pieces: u32,
+    // Number of singles, doubles, triples and tetrises.
+    pub nb_clears: [u32; 4],
+    // Number of T-spin singles, doubles and triples, and of perfect clears.
+    pub nb_tspins: [u32; 3],
+    pub nb_perfect_clears: u32,
+    // Frames played since the start of the game.
+    pub frames: u32,
+    // Progress of the current piece towards the next row, see gravity_step.
+    pub fall: u32,
+    // Frames left before the next piece spawns, and frames the current piece
+    // has spent on the stack when pieces don't lock as soon as they land.
+    pub delay: u32,
+    pub lock_timer: u32,
+    // Master mode scoring: combo counter and rows soft dropped by the current piece.
+    pub combo: u32,
+    pub soft_rows: u32,
+    pub grandmaster: bool,
+    // Time taken to reach every 10 lines in a sprint, in whole frames written
+    // in milliseconds as elapsed_millis.
+    pub splits: Vec<u32>,
+    pub garbage_hole: usize,
+    pub garbage_added: u32,
+    pub garbage_cleared: u32,
+    // Versus mode garbage sent and received.
+    pub attack: Attack,
+    // Set when the stack reached the top or a puzzle ran out of pieces.
+    pub game_over: bool,
+    pub current_piece: Option<Tetrimino>,
+    // Type of the current piece, or of the last one between two pieces.
+    pub last_piece: Option<TetriminoTypes>,
+    // Whether the last successful move of the current piece was a rotation.
+    pub rotated: bool,
+    // Pieces to come, kept as long as the preview of the rules with random
+    // pieces except in puzzles, which give all of their pieces.
+    pub queue: VecDeque<TetriminoTypes>,
+    // Piece put aside, and whether the current piece was already swapped with
+    // it, which can only happen once per piece.
+    pub hold_piece: Option<TetriminoTypes>,
+    pub hold_used: bool,
+    // Moves made by the player with the current piece since it spawned, the
+    // positions it was moved from and to by the gravity or the garbage, with
+    // the number of moves made before, and pieces placed with more shifts and
+    // rotations than needed.
+    pub piece_moves: Vec<Action>,
+    pub piece_pushes: Vec<(Tetrimino, Tetrimino, usize)>,
+    pub finesse_faults: u32,
+    // Source of the random pieces and garbage holes, seeded so that a game can
+    // be played again the same way.
+    pub seed: u64,
+    pub rng: StdRng,
+    // Actions of the player by frame, to play the game again from its seed.
+    pub inputs: Vec<(u32, Vec<Action>)>,
+}
+
+impl Tetris {
+    pub fn new(rules: Rules) -> Tetris {
+        Tetris::with_seed(rules, rand::random())
+    }
+
+    pub fn with_seed(rules: Rules, seed: u64) -> Tetris {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut tetris = Tetris {
+            game_map: Board::default(),
+            rules,
+            current_level: if rules.mode == Mode::Master {
+                0
+            } else {
+                rules.start_level
+            },
+            score: 0,
+            nb_lines: 0,
+            level_lines: 0,
+            nb_pieces: 0,
+            nb_clears: [0; 4],
+            nb_tspins: [0; 3],
+            nb_perfect_clears: 0,
+            frames: 0,
+            fall: 0,
+            delay: 0,
+            lock_timer: 0,
+            combo: 1,
+            soft_rows: 0,
+            grandmaster: true,
+            splits: Vec::new(),
+            garbage_hole: rng.gen_range(0..10),
+            garbage_added: 0,
+            garbage_cleared: 0,
+            attack: Attack::default(),
+            game_over: false,
+            current_piece: None,
+            last_piece: None,
+            rotated: false,
+            queue: VecDeque::new(),
+            hold_piece: None,
+            hold_used: false,
+            piece_moves: Vec::new(),
+            piece_pushes: Vec::new(),
+            finesse_faults: 0,
+            seed,
+            rng,
+            inputs: Vec::new(),
+        };
+        if let Mode::Dig(goal) = rules.mode {
+            tetris.insert_garbage(goal.min(DIG_ROWS));
+        }
+        tetris.fill_queue(rules.preview.min(PREVIEW_PIECES));
+        tetris
+    }
+
+    pub fn from_puzzle(rules: Rules, puzzle: &Puzzle) -> Tetris {
+        let mut tetris = Tetris::new(Rules {
+            mode: Mode::Puzzle(puzzle.goal),
+            ..rules
+        });
+        let offset = tetris.game_map.height() - puzzle.board.len();
+        for (y, row) in puzzle.board.iter().enumerate() {
+            tetris.game_map.set_row(offset + y, row);
+        }
+        tetris.queue = puzzle.queue.iter().copied().collect();
+        tetris
+    }
+
+    // Pushes garbage rows in from the bottom of the map, see attack::insert_garbage.
+    fn insert_garbage(&mut self, nb_rows: u32) {
+        if attack::insert_garbage(
+            &mut self.game_map,
+            nb_rows,
+            &mut self.garbage_hole,
+            self.rules.messiness,
+            &mut self.rng,
+        ) {
+            self.game_over = true;
+        }
+        self.garbage_added += nb_rows;
+
+        if let Some(mut piece) = self.current_piece {
+            while !piece.test_current_position(&self.game_map) && piece.y > 0 {
+                piece.y -= 1;
+            }
+            self.push_piece(piece);
+        }
+    }
+
+    // Draws random pieces into the queue until it is `length` long, never the
+    // same piece twice in a row. Puzzles give all of their pieces instead.
+    fn fill_queue(&mut self, length: usize) {
+        if matches!(self.rules.mode, Mode::Puzzle(_)) {
+            return;
+        }
+        while self.queue.len() < length {
+            let last = self.queue.back().copied().or(self.last_piece);
+            let mut next: TetriminoTypes = self.rng.gen();
+            while Some(next) == last {
+                next = self.rng.gen();
+            }
+            self.queue.push_back(next);
+        }
+    }
+
+    // The next piece, drawn now when the preview doesn't show it yet.
+    fn next_piece(&mut self) -> Option<TetriminoTypes> {
+        self.fill_queue(1);
+        let next = self.queue.pop_front();
+        self.fill_queue(self.rules.preview.min(PREVIEW_PIECES));
+        next
+    }
+
+    fn create_next_tetrimino(&mut self) {
+        if let Some(next) = self.next_piece() {
+            self.last_piece = Some(next);
+            self.current_piece = Some(TetriminoTypes::generate(next));
+        }
+    }
+
+    pub fn can_hold(&self) -> bool {
+        self.rules.hold
+            && !self.hold_used
+            && (self.hold_piece.is_some()
+                || !self.queue.is_empty()
+                || !matches!(self.rules.mode, Mode::Puzzle(_)))
+    }
+
+    // Swaps the current piece with the held one, or with the next piece the
+    // first time, the new piece starting again from the top.
+    fn hold(&mut self) {
+        let current = match self.last_piece {
+            Some(current) if self.current_piece.is_some() && self.can_hold() => current,
+            _ => return,
+        };
+        let next = match self.hold_piece.or_else(|| self.next_piece()) {
+            Some(next) => next,
+            None => return,
+        };
+        self.hold_piece = Some(current);
+        self.hold_used = true;
+        self.last_piece = Some(next);
+        let piece = TetriminoTypes::generate(next);
+        if !piece.test_current_position(&self.game_map) {
+            self.game_over = true;
+        }
+        self.current_piece = Some(piece);
+        self.soft_rows = 0;
+        self.rotated = false;
+        self.piece_moves.clear();
+        self.piece_pushes.clear();
+        self.reset_timer();
+    }
+
+    // Moves the current piece without the player, remembering where from so
+    // that the moves it takes from there are not counted as finesse faults.
+    fn push_piece(&mut self, piece: Tetrimino) {
+        let before = match self.current_piece {
+            Some(before) if before != piece => before,
+            _ => return,
+        };
+        self.current_piece = Some(piece);
+        match self.piece_pushes.last_mut() {
+            Some((_, after, moves)) if *moves == self.piece_moves.len() => *after = piece,
+            _ => self
+                .piece_pushes
+                .push((before, piece, self.piece_moves.len())),
+        }
+    }
+
+    // Whether the current piece was brought where it is with more shifts and
+    // rotations than the fewest getting it there from the top, plus the ones
+    // the gravity or the garbage added by moving it on the way.
+    fn is_finesse_fault(&self) -> bool {
+        let (piece, kind) = match (&self.current_piece, self.last_piece) {
+            (Some(piece), Some(kind)) => (piece, kind),
+            _ => return false,
+        };
+        let moves = self
+            .piece_moves
+            .iter()
+            .filter(|action| **action != Action::SoftDrop)
+            .count() as u32;
+        let fewest = |from: &Tetrimino| movegen::finesse(&self.game_map, from, piece);
+        let mut allowed = match fewest(&kind.generate()) {
+            Some(allowed) => allowed,
+            None => return false,
+        };
+        for (before, after, _) in &self.piece_pushes {
+            match (fewest(before), fewest(after)) {
+                (Some(before), Some(after)) => allowed += after.saturating_sub(before),
+                _ => return false,
+            }
+        }
+        moves > allowed
+    }
+
+    fn spawn_next(&mut self) {
+        if matches!(self.rules.mode, Mode::Puzzle(_)) && self.queue.is_empty() {
+            self.game_over = true;
+            return;
+        }
+        self.create_next_tetrimino();
+        self.soft_rows = 0;
+        self.rotated = false;
+        self.hold_used = false;
+        self.piece_moves.clear();
+        self.piece_pushes.clear();
+        if self.rules.mode == Mode::Master
+            && self.current_level % 100 != 99
+            && self.current_level < rules::MASTER_LAST_LEVEL - 1
+        {
+            self.current_level += 1;
+        }
+        if let Some(ref piece) = self.current_piece {
+            if !piece.test_current_position(&self.game_map) {
+                self.game_over = true;
+            }
+        }
+    }
+
+    fn update_score(&mut self, to_add: u32) {
+        self.score += to_add;
+    }
+
+    fn check_lines(&mut self) -> u32 {
+        let lines = self.game_map.clear_lines();
+        let cleared = lines.len();
+        let mut score_add = self.current_level * cleared as u32;
+        self.garbage_cleared += lines.iter().filter(|line| line.contains(&GARBAGE)).count() as u32;
+        if cleared == self.game_map.height() {
+            // A "tetris"
+            score_add += 1000;
+        }
+        if self.rules.mode != Mode::Master {
+            self.update_score(score_add);
+        }
+        if cleared > 0 {
+            self.nb_clears[cleared.min(4) - 1] += 1;
+        }
+
+        for _ in 0..cleared {
+            self.increase_line();
+        }
+        cleared as u32
+    }
+
+    // A T-spin is a T piece locked right after a rotation, with at least three
+    // of the four cells diagonal to its center taken or out of the map.
+    fn is_tspin(&self) -> bool {
+        let piece = match self.current_piece {
+            Some(ref piece) => piece,
+            None => return false,
+        };
+        if self.last_piece != Some(TetriminoTypes::TetriminoT) || !self.rotated {
+            return false;
+        }
+        let (center_x, center_y) = if piece.rotation == 0 {
+            (piece.x + 1, piece.y as isize)
+        } else {
+            (piece.x + 1, piece.y as isize + 1)
+        };
+        [(-1, -1), (1, -1), (-1, 1), (1, 1)]
+            .iter()
+            .filter(|(decal_x, decal_y)| {
+                let x = center_x + decal_x;
+                let y = center_y + decal_y;
+                !(0..10).contains(&x)
+                    || y >= self.game_map.height() as isize
+                    || (y >= 0 && self.game_map.get(x as usize, y as usize) != 0)
+            })
+            .count()
+            >= 3
+    }
+
+    // Master mode scoring and delays, from the level the piece locked at and
+    // the number of lines it cleared.
+    fn lock_master(&mut self, level: u32, cleared: u32) {
+        let timings = rules::master_timings(level);
+        if cleared == 0 {
+            self.combo = 1;
+            self.delay = timings.are;
+            return;
+        }
+        self.combo += 2 * cleared - 2;
+        let bravo = if self.game_map.is_empty() { 4 } else { 1 };
+        self.update_score(
+            ((level + cleared).div_ceil(4) + self.soft_rows) * cleared * self.combo * bravo,
+        );
+        self.delay = timings.line_clear + timings.line_are;
+
+        let new_level = (level + cleared).min(rules::MASTER_LAST_LEVEL);
+        for (check, score, seconds) in rules::GRANDMASTER_CHECKS {
+            if level < check
+                && new_level >= check
+                && (self.score < score || self.elapsed_millis() > seconds * 1000)
+            {
+                self.grandmaster = false;
+            }
+        }
+        self.current_level = new_level;
+    }
+
+    fn make_permanent(&mut self) {
+        // Only people playing are told about their faults, the search being
+        // too slow to run for every piece of the bots.
+        if self.rules.finesse != Finesse::Off && self.is_finesse_fault() {
+            self.finesse_faults += 1;
+            // In training, the piece starts again from the top instead.
+            if self.rules.finesse == Finesse::Training {
+                if let Some(kind) = self.last_piece {
+                    let piece = kind.generate();
+                    if !piece.test_current_position(&self.game_map) {
+                        self.game_over = true;
+                    }
+                    self.current_piece = Some(piece);
+                }
+                self.rotated = false;
+                self.soft_rows = 0;
+                self.piece_moves.clear();
+                self.piece_pushes.clear();
+                self.reset_timer();
+                return;
+            }
+        }
+        let mut to_add = 0;
+        if let Some(piece) = self.current_piece {
+            for (x, y) in piece.cells() {
+                if y < self.game_map.height() && (0..self.game_map.width() as isize).contains(&x) {
+                    self.game_map.set(x as usize, y, piece.kind.color());
+                }
+            }
+            to_add += self.current_level;
+            self.nb_pieces += 1;
+        }
+        let level = self.current_level;
+        let tspin = self.is_tspin();
+        let cleared = self.check_lines();
+        if self.rules.mode == Mode::Master {
+            self.lock_master(level, cleared);
+        } else {
+            self.update_score(to_add);
+        }
+        let perfect_clear = cleared > 0 && self.game_map.is_empty();
+        if cleared > 0 && tspin {
+            self.nb_tspins[cleared.min(3) as usize - 1] += 1;
+        }
+        if perfect_clear {
+            self.nb_perfect_clears += 1;
+        }
+        self.current_piece = None;
+        // The attack is counted in every mode for the statistics, but only
+        // sent in versus. Received garbage only rises when a piece locks
+        // without clearing lines.
+        self.attack.lock(cleared, tspin, perfect_clear);
+        if cleared == 0 {
+            let rows = self.attack.take_pending();
+            if rows > 0 {
+                self.insert_garbage(rows);
+            }
+        }
+        self.reset_timer();
+    }
+
+    // Time of the frames played so far, so to within a frame.
+    pub fn elapsed_millis(&self) -> u32 {
+        (self.frames as u64 * 1000 / FRAMES_PER_SECOND as u64) as u32
+    }
+
+    // Speed of the gravity as the progress made by a piece every frame and
+    // the progress needed to fall by one row.
+    fn gravity_step(&self) -> (u32, u32) {
+        match self.rules.mode {
+            Mode::Master => (rules::master_gravity(self.current_level), 256),
+            _ => (
+                1000,
+                self.rules.gravity.level_time(self.current_level) * FRAMES_PER_SECOND,
+            ),
+        }
+    }
+
+    // Frames a landed piece waits before locking, if it doesn't lock as soon
+    // as the gravity can't make it fall anymore.
+    fn lock_delay(&self) -> Option<u32> {
+        match self.rules.mode {
+            Mode::Master => Some(rules::master_timings(self.current_level).lock),
+            _ => None,
+        }
+    }
+
+    fn reset_timer(&mut self) {
+        self.fall = 0;
+        self.lock_timer = 0;
+    }
+
+    fn is_landed(&self) -> bool {
+        match self.current_piece {
+            Some(ref piece) => {
+                !piece.test_position(&self.game_map, piece.rotation, piece.x, piece.y + 1)
+            }
+            None => false,
+        }
+    }
+
+    // Lets the current piece fall by as many rows as the gravity allows in a
+    // frame, which can be none or all the way down to the stack.
+    fn apply_gravity(&mut self) {
+        let (speed, row) = self.gravity_step();
+        self.fall += speed;
+        while self.fall >= row {
+            self.fall -= row;
+            if self.is_landed() {
+                self.fall = 0;
+                if self.lock_delay().is_none() {
+                    self.make_permanent();
+                }
+                return;
+            }
+            if let Some(mut piece) = self.current_piece {
+                piece.y += 1;
+                self.push_piece(piece);
+            }
+            self.lock_timer = 0;
+            self.rotated = false;
+        }
+    }
+
+    // Advances the game by one frame: spawns the next piece once the delay
+    // after the last one is over, then lets the gravity act on it.
+    pub fn tick(&mut self) {
+        self.frames += 1;
+        if self.current_piece.is_none() {
+            if self.delay > 0 {
+                self.delay -= 1;
+                return;
+            }
+            self.spawn_next();
+            if self.game_over {
+                return;
+            }
+        }
+
+        self.apply_gravity();
+        if let Some(lock_delay) = self.lock_delay() {
+            if self.is_landed() {
+                self.lock_timer += 1;
+                if self.lock_timer >= lock_delay {
+                    self.make_permanent();
+                }
+            }
+        }
+
+        if let Mode::Dig(goal) = self.rules.mode {
+            if self.garbage_added < goal
+                && self.frames.is_multiple_of(DIG_INTERVAL * FRAMES_PER_SECOND)
+            {
+                self.insert_garbage(1);
+            }
+        }
+    }
+
+    pub fn is_complete(&self) -> bool {
+        match self.rules.mode {
+            Mode::Endless | Mode::Versus | Mode::Coop => false,
+            Mode::Marathon(goal) | Mode::Sprint(goal) => self.nb_lines >= goal,
+            Mode::Ultra(minutes) => self.frames >= minutes * 60 * FRAMES_PER_SECOND,
+            Mode::Dig(goal) => self.garbage_cleared >= goal,
+            Mode::Master => self.current_level >= rules::MASTER_LAST_LEVEL,
+            Mode::Puzzle(Goal::Lines(lines)) => self.nb_lines >= lines,
+            Mode::Puzzle(Goal::PerfectClear) => self.nb_perfect_clears > 0,
+            Mode::Puzzle(Goal::TSpinDouble) => self.nb_tspins[1] > 0,
+        }
+    }
+
+    fn increase_line(&mut self) {
+        self.nb_lines += 1;
+        if let Mode::Sprint(goal) = self.rules.mode {
+            if self.nb_lines.is_multiple_of(10) && self.nb_lines <= goal {
+                self.splits.push(self.elapsed_millis());
+            }
+        }
+        self.level_lines += 1;
+        if self.rules.mode != Mode::Master
+            && self
+                .rules
+                .level_up
+                .is_level_up(self.current_level, self.nb_lines, self.level_lines)
+        {
+            self.current_level += 1;
+            self.level_lines = 0;
+        }
+    }
+}
+
+// Applies the actions of the player to the game, before its next frame.
+pub fn apply_actions(tetris: &mut Tetris, actions: &[Action]) {
+    let logged = actions
+        .iter()
+        .copied()
+        .filter(|action| action.to_char().is_some());
+    match tetris.inputs.last_mut() {
+        Some((frame, frame_actions)) if *frame == tetris.frames => frame_actions.extend(logged),
+        _ => {
+            let logged = logged.collect::<Vec<_>>();
+            if !logged.is_empty() {
+                tetris.inputs.push((tetris.frames, logged));
+            }
+        }
+    }
+    move_piece(tetris, actions);
+}
+
+// Moves the current piece with the actions, the ones after a hold moving the
+// new piece.
+fn move_piece(tetris: &mut Tetris, actions: &[Action]) {
+    let mut make_permanant = false;
+    let mut hold = None;
+    if let Some(ref mut piece) = tetris.current_piece {
+        for (index, action) in actions.iter().enumerate() {
+            match action {
+                Action::SoftDrop => {
+                    tetris.fall = 0;
+                    tetris.lock_timer = 0;
+                    let x = piece.x;
+                    let y = piece.y + 1;
+                    if !piece.change_position(&tetris.game_map, x, y) {
+                        make_permanant = true;
+                        break;
+                    }
+                    tetris.soft_rows += 1;
+                    tetris.rotated = false;
+                    tetris.piece_moves.push(*action);
+                }
+                Action::MoveRight => {
+                    let x = piece.x + 1;
+                    let y = piece.y;
+                    if piece.change_position(&tetris.game_map, x, y) {
+                        tetris.rotated = false;
+                        tetris.piece_moves.push(*action);
+                    }
+                }
+                Action::MoveLeft => {
+                    let x = piece.x - 1;
+                    let y = piece.y;
+                    if piece.change_position(&tetris.game_map, x, y) {
+                        tetris.rotated = false;
+                        tetris.piece_moves.push(*action);
+                    }
+                }
+                Action::Rotate => {
+                    if piece.rotate(&tetris.game_map) {
+                        tetris.rotated = true;
+                        tetris.piece_moves.push(*action);
+                    }
+                }
+                Action::HardDrop => {
+                    let x = piece.x;
+                    let mut y = piece.y;
+                    while piece.change_position(&tetris.game_map, x, y + 1) {
+                        y += 1;
+                        tetris.rotated = false;
+                    }
+                    make_permanant = true;
+                    break;
+                }
+                Action::Hold => {
+                    hold = Some(&actions[index + 1..]);
+                    break;
+                }
+                Action::Quit | Action::Confirm => {}
+            }
+        }
+    }
+    if make_permanant {
+        tetris.make_permanent();
+    }
+    if let Some(rest) = hold {
+        tetris.hold();
+        move_piece(tetris, rest);
+    }
+}
+
+// Moves the garbage each player sent to the other one's pending rows.
+pub fn exchange_garbage(first: &mut Tetris, second: &mut Tetris) {
+    if first.rules.mode != Mode::Versus {
+        return;
+    }
+    first.attack.receive(second.attack.take_outgoing());
+    second.attack.receive(first.attack.take_outgoing());
+}
+
+pub fn is_over(players: &[Tetris]) -> bool {
+    players.iter().any(|tetris| tetris.game_over)
+}
@@ -0,0 +1,394 @@
+/*
+ * This module wraps the engine into an environment for reinforcement
+ * learning, as gym environments do: reset(seed) starts a game and gives its
+ * first observation, and step(action) plays an action and gives the next
+ * observation, the reward of the action, whether the game is done and some
+ * information about it.
+ *
+ * Actions are either the placements of the current piece, every position it
+ * or the held piece can lock in as found by movegen, or the actions of a
+ * single frame as a player would make them. Observations are vectors of
+ * numbers between 0 and 1 made of the encodings chosen in ObservationConfig,
+ * in the order of its fields, and rewards are the sums of the terms of
+ * RewardConfig, to shape them as wanted. The games are the ones of the rules
+ * given, and are played and recorded like any other.
+ */
+use crate::ai;
+use crate::bot::BotGame;
+use crate::engine::{Action, TetriminoTypes, Tetris, PREVIEW_PIECES};
+use crate::movegen::Move;
+use crate::rules::Rules;
+
+// Actions of a frame, by index, when the actions are frames.
+pub const FRAME_ACTIONS: [Option<Action>; 7] = [
+    None,
+    Some(Action::MoveLeft),
+    Some(Action::MoveRight),
+    Some(Action::SoftDrop),
+    Some(Action::HardDrop),
+    Some(Action::Rotate),
+    Some(Action::Hold),
+];
+const NB_PIECE_TYPES: usize = 7;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ActionSpace {
+    // An action is the index of a move in Env::moves, the moves of the held
+    // piece coming after the ones of the current piece.
+    Placements,
+    // An action is the index of the action of the frame in FRAME_ACTIONS.
+    Frames,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct ObservationConfig {
+    // Every cell of the map, 1 for a block, row by row from the top.
+    pub board: bool,
+    // The cells of the current piece, as the board.
+    pub current_piece: bool,
+    // Height of every column, as a share of the height of the map.
+    pub heights: bool,
+    // Holes, bumpiness and wells of the board, as in ai::Features, each as a
+    // share of the cells of the map.
+    pub features: bool,
+    // The type of the current piece, one-hot.
+    pub piece: bool,
+    // The types of the next pieces, one-hot each, up to PREVIEW_PIECES.
+    pub queue: usize,
+    // The type of the held piece, one-hot and all 0 without one, then 1 if
+    // the current piece can be held.
+    pub hold: bool,
+}
+
+impl Default for ObservationConfig {
+    fn default() -> ObservationConfig {
+        ObservationConfig {
+            board: true,
+            current_piece: false,
+            heights: true,
+            features: false,
+            piece: true,
+            queue: PREVIEW_PIECES,
+            hold: true,
+        }
+    }
+}
+
+// The reward of an action is the sum of these terms.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct RewardConfig {
+    // By number of lines cleared, from none to four.
+    pub lines: [f64; 5],
+    // By point scored, and by piece placed.
+    pub score: f64,
+    pub piece: f64,
+    // When the game is lost.
+    pub game_over: f64,
+    // By change of the height, holes and bumpiness of the board, as in
+    // ai::Features, negative ones penalizing a board getting worse.
+    pub height: f64,
+    pub holes: f64,
+    pub bumpiness: f64,
+}
+
+impl Default for RewardConfig {
+    fn default() -> RewardConfig {
+        RewardConfig {
+            lines: [0.0, 1.0, 3.0, 5.0, 8.0],
+            score: 0.0,
+            piece: 0.01,
+            game_over: -10.0,
+            height: 0.0,
+            holes: 0.0,
+            bumpiness: 0.0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct EnvConfig {
+    pub rules: Rules,
+    pub actions: ActionSpace,
+    pub observation: ObservationConfig,
+    pub reward: RewardConfig,
+    // Pieces after which a game is done, for modes which could go on forever.
+    pub max_pieces: Option<u32>,
+}
+
+impl Default for EnvConfig {
+    fn default() -> EnvConfig {
+        // The whole preview and the hold, as observed by default.
+        EnvConfig {
+            rules: Rules {
+                preview: PREVIEW_PIECES,
+                hold: true,
+                ..Rules::default()
+            },
+            actions: ActionSpace::Placements,
+            observation: ObservationConfig::default(),
+            reward: RewardConfig::default(),
+            max_pieces: None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Info {
+    pub score: u32,
+    pub lines: u32,
+    pub pieces: u32,
+    pub frames: u32,
+    // Lines cleared by the action.
+    pub cleared: u32,
+    // Whether the game was lost, rather than completed or stopped.
+    pub topped_out: bool,
+}
+
+pub type Observation = Vec<f32>;
+
+// What the reward of an action is worked out from, before the action.
+struct Snapshot {
+    score: u32,
+    lines: u32,
+    pieces: u32,
+    game_over: bool,
+    features: Option<ai::Features>,
+}
+
+pub struct Env {
+    pub config: EnvConfig,
+    game: BotGame,
+    // The moves the placements are the indexes of, for the current piece.
+    moves: Vec<Move>,
+}
+
+fn push_one_hot(observation: &mut Observation, piece: Option<TetriminoTypes>) {
+    let start = observation.len();
+    observation.resize(start + NB_PIECE_TYPES, 0.0);
+    if let Some(piece) = piece {
+        observation[start + piece as usize] = 1.0;
+    }
+}
+
+impl Env {
+    pub fn new(config: EnvConfig) -> Env {
+        let mut env = Env {
+            config,
+            game: BotGame::new(config.rules, 0),
+            moves: Vec::new(),
+        };
+        env.update_moves();
+        env
+    }
+
+    // Starts a new game from the seed and gives its first observation.
+    pub fn reset(&mut self, seed: u64) -> Observation {
+        self.game = BotGame::new(self.config.rules, seed);
+        self.update_moves();
+        self.observation()
+    }
+
+    pub fn game(&self) -> &Tetris {
+        &self.game.tetris
+    }
+
+    pub fn moves(&self) -> &[Move] {
+        &self.moves
+    }
+
+    // Number of actions possible, the legal ones for placements.
+    pub fn action_count(&self) -> usize {
+        match self.config.actions {
+            ActionSpace::Placements => self.moves.len(),
+            ActionSpace::Frames => FRAME_ACTIONS.len(),
+        }
+    }
+
+    // Length of the observations, which is the same for every state.
+    pub fn observation_size(&self) -> usize {
+        self.observation().len()
+    }
+
+    fn update_moves(&mut self) {
+        self.moves = match self.config.actions {
+            ActionSpace::Placements => {
+                let mut moves = self.game.moves(false);
+                if self.game.can_hold() {
+                    moves.extend(self.game.moves(true));
+                }
+                moves
+            }
+            ActionSpace::Frames => Vec::new(),
+        };
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.game.is_over()
+            || self
+                .config
+                .max_pieces
+                .is_some_and(|max| self.game.tetris.nb_pieces >= max)
+    }
+
+    // Plays an action and gives the next observation, the reward of the
+    // action, whether the game is done and information about it. A placement
+    // out of range hard drops the current piece where it is, and a frame
+    // action out of range is no action.
+    pub fn step(&mut self, action: usize) -> (Observation, f64, bool, Info) {
+        let before = self.snapshot();
+        if !self.is_done() {
+            self.play(&self.actions(action));
+            self.update_moves();
+        }
+        self.outcome(&before)
+    }
+
+    // What step would give for the action, without playing it, to look ahead.
+    pub fn peek(&self, action: usize) -> (Observation, f64, bool, Info) {
+        let mut env = Env {
+            config: self.config,
+            game: BotGame {
+                tetris: self.game.tetris.clone(),
+            },
+            moves: Vec::new(),
+        };
+        let before = env.snapshot();
+        if !env.is_done() {
+            env.play(&self.actions(action));
+        }
+        env.outcome(&before)
+    }
+
+    // The actions of the placement or of the frame an action stands for.
+    fn actions(&self, action: usize) -> Vec<Action> {
+        match self.config.actions {
+            ActionSpace::Placements => self
+                .moves
+                .get(action)
+                .map_or(vec![Action::HardDrop], |m| m.actions.clone()),
+            ActionSpace::Frames => FRAME_ACTIONS
+                .get(action)
+                .copied()
+                .flatten()
+                .into_iter()
+                .collect(),
+        }
+    }
+
+    fn play(&mut self, actions: &[Action]) {
+        match self.config.actions {
+            ActionSpace::Placements => self.game.play_actions(actions),
+            ActionSpace::Frames => {
+                self.game.step(actions);
+                // There is nothing to do until the next piece spawns.
+                self.game.wait_for_piece();
+            }
+        }
+    }
+
+    fn outcome(&self, before: &Snapshot) -> (Observation, f64, bool, Info) {
+        let tetris = &self.game.tetris;
+        let info = Info {
+            score: tetris.score,
+            lines: tetris.nb_lines,
+            pieces: tetris.nb_pieces,
+            frames: tetris.frames,
+            cleared: tetris.nb_lines - before.lines,
+            topped_out: tetris.game_over,
+        };
+        (
+            self.observation(),
+            self.reward(before),
+            self.is_done(),
+            info,
+        )
+    }
+
+    fn snapshot(&self) -> Snapshot {
+        let config = &self.config.reward;
+        let tetris = &self.game.tetris;
+        Snapshot {
+            score: tetris.score,
+            lines: tetris.nb_lines,
+            pieces: tetris.nb_pieces,
+            game_over: tetris.game_over,
+            features: (config.height != 0.0 || config.holes != 0.0 || config.bumpiness != 0.0)
+                .then(|| ai::features(&tetris.game_map)),
+        }
+    }
+
+    fn reward(&self, before: &Snapshot) -> f64 {
+        let config = &self.config.reward;
+        let tetris = &self.game.tetris;
+        let cleared = (tetris.nb_lines - before.lines).min(4) as usize;
+        let mut reward = config.lines[cleared]
+            + config.score * (tetris.score - before.score) as f64
+            + config.piece * (tetris.nb_pieces - before.pieces) as f64;
+        if tetris.game_over && !before.game_over {
+            reward += config.game_over;
+        }
+        if let Some(ref old) = before.features {
+            let new = ai::features(&tetris.game_map);
+            let change = |old: usize, new: usize| new as f64 - old as f64;
+            reward += config.height * change(old.height, new.height)
+                + config.holes * change(old.holes, new.holes)
+                + config.bumpiness * change(old.bumpiness, new.bumpiness);
+        }
+        reward
+    }
+
+    pub fn observation(&self) -> Observation {
+        let config = &self.config.observation;
+        let tetris = &self.game.tetris;
+        let rows = tetris.game_map.height();
+        let width = tetris.game_map.width();
+        let cells = (rows * width) as f32;
+        let mut observation = Vec::new();
+        if config.board {
+            observation.extend(
+                tetris
+                    .game_map
+                    .color_rows()
+                    .iter()
+                    .flatten()
+                    .map(|case| (*case != 0) as u8 as f32),
+            );
+        }
+        if config.current_piece {
+            let start = observation.len();
+            observation.resize(start + rows * width, 0.0);
+            if let Some(ref piece) = tetris.current_piece {
+                for (x, y) in piece.cells() {
+                    if y < rows && (0..width as isize).contains(&x) {
+                        observation[start + y * width + x as usize] = 1.0;
+                    }
+                }
+            }
+        }
+        if config.heights || config.features {
+            let features = ai::features(&tetris.game_map);
+            if config.heights {
+                observation.extend(features.heights.iter().map(|h| *h as f32 / rows as f32));
+            }
+            if config.features {
+                observation.extend([
+                    features.holes as f32 / cells,
+                    features.bumpiness as f32 / cells,
+                    features.wells as f32 / cells,
+                ]);
+            }
+        }
+        if config.piece {
+            let current = tetris.current_piece.as_ref().and(tetris.last_piece);
+            push_one_hot(&mut observation, current);
+        }
+        for i in 0..config.queue.min(PREVIEW_PIECES) {
+            push_one_hot(&mut observation, tetris.queue.get(i).copied());
+        }
+        if config.hold {
+            push_one_hot(&mut observation, tetris.hold_piece);
+            observation.push(tetris.can_hold() as u8 as f32);
+        }
+        observation
+    }
+}
@@ -0,0 +1,440 @@
+/*
+ * This module turns keyboard and game controller events into game actions.
+ * Bindings are read from the plaintext file defined in BINDINGS_FILE_PATH, one
+ * "<action> <key|button> <name>" entry per line. Key names are the SDL key names
+ * ("Left", "Space", ...) and button names the SDL controller mapping names
+ * ("a", "dpdown", ...). Actions listed in the file replace their default
+ * bindings on the device of the entry, keys or buttons.
+ * In two player modes, each player gets a half of the keyboard, bound with the
+ * "p1-" and "p2-" prefixed actions ("p1-left key A"), and controllers go to the
+ * players in the order they were connected.
+ */
+use sdl2::controller::{Axis, Button, GameController};
+use sdl2::event::Event;
+use sdl2::keyboard::Keycode;
+use sdl2::GameControllerSubsystem;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Read;
+use std::time::{Duration, Instant};
+use tetris::engine::Action;
+
+const BINDINGS_FILE_PATH: &str = "controls.txt";
+const AXIS_DEADZONE: i16 = 10_000;
+const REPEAT_DELAY: Duration = Duration::from_millis(170);
+const REPEAT_INTERVAL: Duration = Duration::from_millis(50);
+
+fn repeats(action: Action) -> bool {
+    matches!(
+        action,
+        Action::MoveLeft | Action::MoveRight | Action::SoftDrop
+    )
+}
+
+pub struct Bindings {
+    keys: Vec<(Keycode, Action)>,
+    // The keyboard halves of the two players, Quit and Confirm staying shared.
+    player_keys: [Vec<(Keycode, Action)>; 2],
+    buttons: Vec<(Button, Action)>,
+}
+
+impl Bindings {
+    fn default_bindings() -> Bindings {
+        Bindings {
+            keys: vec![
+                (Keycode::Left, Action::MoveLeft),
+                (Keycode::Right, Action::MoveRight),
+                (Keycode::Down, Action::SoftDrop),
+                (Keycode::Space, Action::HardDrop),
+                (Keycode::Up, Action::Rotate),
+                (Keycode::C, Action::Hold),
+                (Keycode::Return, Action::Confirm),
+                (Keycode::Escape, Action::Quit),
+            ],
+            player_keys: [
+                vec![
+                    (Keycode::A, Action::MoveLeft),
+                    (Keycode::D, Action::MoveRight),
+                    (Keycode::S, Action::SoftDrop),
+                    (Keycode::Space, Action::HardDrop),
+                    (Keycode::W, Action::Rotate),
+                    (Keycode::LShift, Action::Hold),
+                ],
+                vec![
+                    (Keycode::Left, Action::MoveLeft),
+                    (Keycode::Right, Action::MoveRight),
+                    (Keycode::Down, Action::SoftDrop),
+                    (Keycode::RCtrl, Action::HardDrop),
+                    (Keycode::Up, Action::Rotate),
+                    (Keycode::RShift, Action::Hold),
+                ],
+            ],
+            buttons: vec![
+                (Button::DPadLeft, Action::MoveLeft),
+                (Button::DPadRight, Action::MoveRight),
+                (Button::DPadDown, Action::SoftDrop),
+                (Button::DPadUp, Action::HardDrop),
+                (Button::A, Action::Rotate),
+                (Button::B, Action::Rotate),
+                (Button::LeftShoulder, Action::Hold),
+                (Button::RightShoulder, Action::Hold),
+                (Button::Start, Action::Confirm),
+                (Button::Back, Action::Quit),
+            ],
+        }
+    }
+
+    pub fn load() -> Bindings {
+        let mut content = String::new();
+        if File::open(BINDINGS_FILE_PATH)
+            .and_then(|mut f| f.read_to_string(&mut content))
+            .is_err()
+        {
+            return Bindings::default_bindings();
+        }
+        Bindings::parse(&content)
+    }
+
+    // The default bindings with the ones of the file content in their place.
+    fn parse(content: &str) -> Bindings {
+        let mut bindings = Bindings::default_bindings();
+        let mut rebound = Vec::new();
+        for line in content.lines() {
+            let words = line.split_whitespace().collect::<Vec<_>>();
+            if words.len() != 3 {
+                continue;
+            }
+            let (player, name) = match words[0].split_once('-') {
+                Some(("p1", name)) => (Some(0), name),
+                Some(("p2", name)) => (Some(1), name),
+                _ => (None, words[0]),
+            };
+            let action = match Action::from_name(name) {
+                Some(action) => action,
+                None => continue,
+            };
+            // Only the bindings of the device the line names are replaced.
+            let device = words[1];
+            if !rebound.contains(&(player, device, action)) {
+                match (device, player) {
+                    ("key", Some(player)) => {
+                        bindings.player_keys[player].retain(|(_, a)| *a != action)
+                    }
+                    ("key", None) => bindings.keys.retain(|(_, a)| *a != action),
+                    ("button", None) => bindings.buttons.retain(|(_, a)| *a != action),
+                    _ => continue,
+                }
+                rebound.push((player, device, action));
+            }
+            match (device, player) {
+                ("key", Some(player)) => {
+                    if let Some(key) = Keycode::from_name(words[2]) {
+                        bindings.player_keys[player].push((key, action));
+                    }
+                }
+                ("key", None) => {
+                    if let Some(key) = Keycode::from_name(words[2]) {
+                        bindings.keys.push((key, action));
+                    }
+                }
+                ("button", None) => {
+                    if let Some(button) = Button::from_string(words[2]) {
+                        bindings.buttons.push((button, action));
+                    }
+                }
+                _ => {}
+            }
+        }
+        bindings
+    }
+
+    pub fn key_action(&self, key: Keycode) -> Option<Action> {
+        self.keys.iter().find(|(k, _)| *k == key).map(|(_, a)| *a)
+    }
+
+    // The player a key belongs to when the keyboard is shared, and its action.
+    pub fn player_key_action(&self, key: Keycode) -> Option<(usize, Action)> {
+        self.player_keys
+            .iter()
+            .enumerate()
+            .find_map(|(player, keys)| {
+                keys.iter()
+                    .find(|(k, _)| *k == key)
+                    .map(|(_, a)| (player, *a))
+            })
+            .or_else(|| {
+                self.key_action(key)
+                    .filter(|a| matches!(a, Action::Quit | Action::Confirm))
+                    .map(|a| (0, a))
+            })
+    }
+
+    pub fn button_action(&self, button: Button) -> Option<Action> {
+        self.buttons
+            .iter()
+            .find(|(b, _)| *b == button)
+            .map(|(_, a)| *a)
+    }
+}
+
+fn axis_direction(value: i16) -> i8 {
+    if value > AXIS_DEADZONE {
+        1
+    } else if value < -AXIS_DEADZONE {
+        -1
+    } else {
+        0
+    }
+}
+
+// The actions released and pressed when a stick axis goes from the direction
+// old to new, given the actions of its negative and positive directions.
+fn stick_change(
+    old: i8,
+    new: i8,
+    negative: Option<Action>,
+    positive: Action,
+) -> (Option<Action>, Option<Action>) {
+    let action = |direction: i8| match direction {
+        1 => Some(positive),
+        -1 => negative,
+        _ => None,
+    };
+    if old == new {
+        (None, None)
+    } else {
+        (action(old), action(new))
+    }
+}
+
+// The actions released and pressed when an axis of a stick, whose directions
+// are (x, y), moves to the value. The left stick moves the piece and soft
+// drops it, the other axes do nothing.
+fn stick_motion(stick: &mut (i8, i8), axis: Axis, value: i16) -> (Option<Action>, Option<Action>) {
+    let (direction, negative, positive) = match axis {
+        Axis::LeftX => (&mut stick.0, Some(Action::MoveLeft), Action::MoveRight),
+        Axis::LeftY => (&mut stick.1, None, Action::SoftDrop),
+        _ => return (None, None),
+    };
+    let old = *direction;
+    *direction = axis_direction(value);
+    stick_change(old, *direction, negative, positive)
+}
+
+pub struct Controls {
+    bindings: Bindings,
+    players: usize,
+    subsystem: GameControllerSubsystem,
+    controllers: Vec<GameController>,
+    // Direction of the left stick of every controller, by joystick instance id.
+    sticks: HashMap<u32, (i8, i8)>,
+    // Controllers have no key repeat, so held actions are repeated here.
+    held: Vec<(u32, Action, Instant)>,
+}
+
+impl Controls {
+    pub fn new(bindings: Bindings, subsystem: GameControllerSubsystem, players: usize) -> Controls {
+        Controls {
+            bindings,
+            players,
+            subsystem,
+            controllers: Vec::new(),
+            sticks: HashMap::new(),
+            held: Vec::new(),
+        }
+    }
+
+    // Controllers go to the players in the order they were connected, the
+    // extra ones to the last player.
+    fn controller_player(&self, which: u32) -> usize {
+        self.controllers
+            .iter()
+            .position(|c| c.instance_id() == which)
+            .unwrap_or(0)
+            .min(self.players - 1)
+    }
+
+    fn press(&mut self, which: u32, action: Action, actions: &mut Vec<(usize, Action)>) {
+        actions.push((self.controller_player(which), action));
+        if repeats(action) {
+            self.held
+                .push((which, action, Instant::now() + REPEAT_DELAY));
+        }
+    }
+
+    fn release(&mut self, which: u32, action: Action) {
+        self.held.retain(|(w, a, _)| *w != which || *a != action);
+    }
+
+    fn move_stick(
+        &mut self,
+        which: u32,
+        axis: Axis,
+        value: i16,
+        actions: &mut Vec<(usize, Action)>,
+    ) {
+        let stick = self.sticks.entry(which).or_insert((0, 0));
+        let (released, pressed) = stick_motion(stick, axis, value);
+        if let Some(action) = released {
+            self.release(which, action);
+        }
+        if let Some(action) = pressed {
+            self.press(which, action, actions);
+        }
+    }
+
+    pub fn poll_actions(&mut self, event_pump: &mut sdl2::EventPump) -> Vec<Action> {
+        self.poll_player_actions(event_pump)
+            .into_iter()
+            .map(|(_, action)| action)
+            .collect()
+    }
+
+    // Actions along with the index of the player who made them.
+    pub fn poll_player_actions(
+        &mut self,
+        event_pump: &mut sdl2::EventPump,
+    ) -> Vec<(usize, Action)> {
+        let mut actions = Vec::new();
+        for event in event_pump.poll_iter() {
+            match event {
+                Event::Quit { .. } => actions.push((0, Action::Quit)),
+                Event::KeyDown {
+                    keycode: Some(key), ..
+                } => {
+                    let action = if self.players > 1 {
+                        self.bindings.player_key_action(key)
+                    } else {
+                        self.bindings.key_action(key).map(|a| (0, a))
+                    };
+                    if let Some(action) = action {
+                        actions.push(action);
+                    }
+                }
+                Event::ControllerDeviceAdded { which, .. } => {
+                    if let Ok(controller) = self.subsystem.open(which) {
+                        self.controllers.push(controller);
+                    }
+                }
+                Event::ControllerDeviceRemoved { which, .. } => {
+                    self.controllers.retain(|c| c.instance_id() != which);
+                    self.sticks.remove(&which);
+                    self.held.retain(|(w, _, _)| *w != which);
+                }
+                Event::ControllerButtonDown { which, button, .. } => {
+                    if let Some(action) = self.bindings.button_action(button) {
+                        self.press(which, action, &mut actions);
+                    }
+                }
+                Event::ControllerButtonUp { which, button, .. } => {
+                    if let Some(action) = self.bindings.button_action(button) {
+                        self.release(which, action);
+                    }
+                }
+                Event::ControllerAxisMotion {
+                    which, axis, value, ..
+                } => self.move_stick(which, axis, value, &mut actions),
+                _ => {}
+            }
+        }
+
+        let now = Instant::now();
+        for i in 0..self.held.len() {
+            let (which, action, next) = self.held[i];
+            if now >= next {
+                actions.push((self.controller_player(which), action));
+                self.held[i].2 = now + REPEAT_INTERVAL;
+            }
+        }
+        actions
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_replaces_default_bindings() {
+        let bindings = Bindings::parse(
+            "rotate key X\nrotate key Z\nhold button x\nnot-an-action key A\nbroken line\n",
+        );
+        assert_eq!(bindings.key_action(Keycode::X), Some(Action::Rotate));
+        assert_eq!(bindings.key_action(Keycode::Z), Some(Action::Rotate));
+        assert_eq!(bindings.key_action(Keycode::Up), None);
+        assert_eq!(bindings.key_action(Keycode::Left), Some(Action::MoveLeft));
+        assert_eq!(bindings.button_action(Button::X), Some(Action::Hold));
+        assert_eq!(bindings.button_action(Button::LeftShoulder), None);
+        // Rebinding the buttons of an action leaves its keys as they were.
+        assert_eq!(bindings.key_action(Keycode::C), Some(Action::Hold));
+        assert_eq!(bindings.button_action(Button::A), Some(Action::Rotate));
+    }
+
+    #[test]
+    fn parse_player_bindings() {
+        let bindings = Bindings::parse("p2-drop key Return\np1-left key Q\n");
+        assert_eq!(
+            bindings.player_key_action(Keycode::Return),
+            Some((1, Action::HardDrop))
+        );
+        assert_eq!(
+            bindings.player_key_action(Keycode::Q),
+            Some((0, Action::MoveLeft))
+        );
+        assert_eq!(bindings.player_key_action(Keycode::RCtrl), None);
+        // The shared keys of the keyboard are left as they were.
+        assert_eq!(bindings.key_action(Keycode::Space), Some(Action::HardDrop));
+        assert_eq!(
+            bindings.player_key_action(Keycode::Escape),
+            Some((0, Action::Quit))
+        );
+    }
+
+    #[test]
+    fn stick_deadzone() {
+        assert_eq!(axis_direction(0), 0);
+        assert_eq!(axis_direction(AXIS_DEADZONE), 0);
+        assert_eq!(axis_direction(-AXIS_DEADZONE), 0);
+        assert_eq!(axis_direction(AXIS_DEADZONE + 1), 1);
+        assert_eq!(axis_direction(-AXIS_DEADZONE - 1), -1);
+        assert_eq!(axis_direction(i16::MAX), 1);
+        assert_eq!(axis_direction(i16::MIN), -1);
+    }
+
+    #[test]
+    fn stick_moves() {
+        let x = |old, new| stick_change(old, new, Some(Action::MoveLeft), Action::MoveRight);
+        assert_eq!(x(0, 1), (None, Some(Action::MoveRight)));
+        assert_eq!(x(1, 1), (None, None));
+        assert_eq!(x(1, 0), (Some(Action::MoveRight), None));
+        assert_eq!(x(1, -1), (Some(Action::MoveRight), Some(Action::MoveLeft)));
+        // Up on the stick does nothing, down soft drops.
+        let y = |old, new| stick_change(old, new, None, Action::SoftDrop);
+        assert_eq!(y(0, -1), (None, None));
+        assert_eq!(y(0, 1), (None, Some(Action::SoftDrop)));
+        assert_eq!(y(1, -1), (Some(Action::SoftDrop), None));
+    }
+
+    #[test]
+    fn stick_axis_motion() {
+        let mut stick = (0, 0);
+        let mut motion = |axis, value| stick_motion(&mut stick, axis, value);
+        assert_eq!(motion(Axis::LeftX, 5_000), (None, None));
+        assert_eq!(motion(Axis::LeftX, 20_000), (None, Some(Action::MoveRight)));
+        assert_eq!(motion(Axis::LeftX, 30_000), (None, None));
+        assert_eq!(
+            motion(Axis::LeftX, -20_000),
+            (Some(Action::MoveRight), Some(Action::MoveLeft))
+        );
+        assert_eq!(
+            motion(Axis::LeftY, i16::MAX),
+            (None, Some(Action::SoftDrop))
+        );
+        assert_eq!(motion(Axis::RightX, i16::MAX), (None, None));
+        assert_eq!(motion(Axis::LeftX, 0), (Some(Action::MoveLeft), None));
+        assert_eq!(
+            motion(Axis::LeftY, i16::MIN),
+            (Some(Action::SoftDrop), None)
+        );
+    }
+}
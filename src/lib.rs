@@ -0,0 +1,19 @@
+/*
+ * The game engine, without any frontend, and the code shared by the game, the
+ * network play server and the tools working on games.
+ */
+pub mod ai;
+pub mod attack;
+pub mod batch;
+pub mod board;
+pub mod bot;
+pub mod cli;
+pub mod engine;
+pub mod env;
+pub mod movegen;
+pub mod net;
+pub mod puzzle;
+pub mod replay;
+pub mod rollback;
+pub mod rules;
+pub mod tbp;
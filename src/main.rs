@@ -1,19 +1,32 @@
-use rand::{
-    distributions::{Distribution, Standard},
-    Rng,
-};
-use sdl2::event::Event;
-use sdl2::keyboard::Keycode;
+mod input;
+mod options;
+
+use input::{Bindings, Controls};
 use sdl2::pixels::Color;
 use sdl2::rect::Rect;
-use sdl2::render::{Canvas, Texture, TextureCreator};
+use sdl2::render::{BlendMode, Canvas, Texture, TextureCreator};
 use sdl2::video::{Window, WindowContext};
+use std::collections::VecDeque;
+use std::process;
+use std::sync::{Arc, Mutex};
 use std::thread::sleep;
-use std::time::{Duration, SystemTime};
+use std::time::{Duration, Instant};
+use tetris::ai::{Ai, Weights};
+use tetris::bot::Agent;
+use tetris::engine::{
+    apply_actions, exchange_garbage, is_over, Action, TetriminoTypes, Tetris, FRAMES_PER_SECOND,
+    PREVIEW_PIECES,
+};
+use tetris::net::{decode_actions, encode_actions, Broadcast, Connection, Message, Stream};
+use tetris::puzzle::{self, Goal, Puzzle};
+use tetris::replay::{Playback, Replay};
+use tetris::rollback::Rollback;
+use tetris::rules::{self, Finesse, Mode, Rules};
+use tetris::tbp::TbpBot;
 
 const TETRIS_HEIGHT: usize = 40;
-const LEVEL_TIMES: [u32; 10] = [1000, 850, 700, 600, 500, 400, 300, 250, 221, 190];
-const LEVEL_LINES: [u32; 10] = [20, 40, 60, 80, 100, 120, 140, 160, 180, 200];
+// Seconds the last board of a demo game stays on screen before the next one.
+const DEMO_RESTART_DELAY: u32 = 3;
 
 fn create_texture_rect<'a>(
     canvas: &mut Canvas<Window>,
@@ -40,6 +53,9 @@ mod score {
     /*
      * This module contains the code to handle high score reading and writing.
      * High score is stored as plaintext in the path defined in SAVE_FILE_PATH.
+     * Marathon and ultra scores and sprint and dig best times are stored the
+     * same way, one file per line goal or time limit. Times are the frames
+     * the game took, written in milliseconds.
      * Number of high scores retained is defined in NB_HIGHSCORES
      */
     use std::fs::File;
@@ -97,6 +113,84 @@ mod score {
         None
     }
 
+    fn load_leaderboard(file_name: &str) -> Vec<u32> {
+        read_from_file(file_name)
+            .map(|content| line_to_slice(content.trim_end()))
+            .unwrap_or_default()
+    }
+
+    // Adds a value to a leaderboard kept best first, returning its rank if it made it in.
+    fn save_to_leaderboard(file_name: &str, value: u32, lower_is_better: bool) -> Option<usize> {
+        let mut leaderboard = load_leaderboard(file_name);
+        let rank = leaderboard
+            .iter()
+            .position(|v| {
+                if lower_is_better {
+                    value < *v
+                } else {
+                    value > *v
+                }
+            })
+            .unwrap_or(leaderboard.len());
+        if rank >= NB_HIGHSCORES {
+            return None;
+        }
+        leaderboard.insert(rank, value);
+        leaderboard.truncate(NB_HIGHSCORES);
+        write_into_file(format!("{}\n", slice_to_string(&leaderboard)), file_name)
+            .ok()
+            .map(|_| rank + 1)
+    }
+
+    fn sprint_file_path(lines: u32) -> String {
+        format!("sprint_{}.txt", lines)
+    }
+
+    pub fn load_sprint_times(lines: u32) -> Vec<u32> {
+        load_leaderboard(&sprint_file_path(lines))
+    }
+
+    // Sprint times are in milliseconds, counted in whole frames, the fastest first.
+    pub fn save_sprint_time(lines: u32, time: u32) -> Option<usize> {
+        save_to_leaderboard(&sprint_file_path(lines), time, true)
+    }
+
+    fn marathon_file_path(lines: u32) -> String {
+        format!("marathon_{}.txt", lines)
+    }
+
+    pub fn load_marathon_scores(lines: u32) -> Vec<u32> {
+        load_leaderboard(&marathon_file_path(lines))
+    }
+
+    pub fn save_marathon_score(lines: u32, score: u32) -> Option<usize> {
+        save_to_leaderboard(&marathon_file_path(lines), score, false)
+    }
+
+    fn dig_file_path(lines: u32) -> String {
+        format!("dig_{}.txt", lines)
+    }
+
+    pub fn load_dig_times(lines: u32) -> Vec<u32> {
+        load_leaderboard(&dig_file_path(lines))
+    }
+
+    pub fn save_dig_time(lines: u32, time: u32) -> Option<usize> {
+        save_to_leaderboard(&dig_file_path(lines), time, true)
+    }
+
+    fn ultra_file_path(minutes: u32) -> String {
+        format!("ultra_{}.txt", minutes)
+    }
+
+    pub fn load_ultra_scores(minutes: u32) -> Vec<u32> {
+        load_leaderboard(&ultra_file_path(minutes))
+    }
+
+    pub fn save_ultra_score(minutes: u32, score: u32) -> Option<usize> {
+        save_to_leaderboard(&ultra_file_path(minutes), score, false)
+    }
+
     pub fn update_vec(v: &mut Vec<u32>, value: u32) -> bool {
         if v.len() < NB_HIGHSCORES {
             v.push(value);
@@ -114,450 +208,296 @@ mod score {
     }
 }
 
-type Piece = Vec<Vec<u8>>;
-type States = Vec<Piece>;
-#[derive(Clone)]
-struct Tetrimino {
-    states: States,
-    x: isize,
-    y: usize,
-    current_state: u8,
+// With an agent playing the game, the player can only quit.
+fn handle_events(
+    tetris: &mut Tetris,
+    quit: &mut bool,
+    agent: Option<&mut (dyn Agent + '_)>,
+    event_pump: &mut sdl2::EventPump,
+    controls: &mut Controls,
+) {
+    let mut actions = controls.poll_actions(event_pump);
+    if actions.contains(&Action::Quit) {
+        *quit = true;
+        return;
+    }
+    if let Some(agent) = agent {
+        actions = agent.actions(tetris);
+    }
+    apply_actions(tetris, &actions);
 }
 
-impl Tetrimino {
-    fn rotate(&mut self, game_map: &[Vec<u8>]) {
-        let mut tmp_state = self.current_state + 1;
-        if tmp_state >= self.states.len() as u8 {
-            tmp_state = 0;
-        }
-        let x_pos = [0, -1, 1, -2, 2, -3];
-        for x in x_pos.iter() {
-            if self.test_position(game_map, tmp_state, self.x + x, self.y) {
-                self.current_state = tmp_state;
-                self.x += *x;
-                break;
-            }
-        }
+fn format_time(millis: u32) -> String {
+    format!(
+        "{}:{:02}.{:03}",
+        millis / 60_000,
+        millis / 1000 % 60,
+        millis % 1000
+    )
+}
+
+fn ultra_time_left(tetris: &Tetris, minutes: u32) -> u32 {
+    (minutes * 60_000).saturating_sub(tetris.elapsed_millis())
+}
+
+fn grade(tetris: &Tetris) -> &'static str {
+    if tetris.is_complete() && tetris.grandmaster {
+        "GM"
+    } else {
+        rules::master_grade(tetris.score)
     }
+}
 
-    fn test_position(&self, game_map: &[Vec<u8>], tmp_state: u8, x: isize, y: usize) -> bool {
-        for decal_y in 0..4 {
-            for decal_x in 0..4 {
-                let x = x + decal_x;
-                if self.states[tmp_state as usize][decal_y][decal_x as usize] != 0
-                    && (y + decal_y >= game_map.len()
-                        || x < 0
-                        || x as usize >= game_map[y + decal_y].len()
-                        || game_map[y + decal_y][x as usize] != 0)
-                {
-                    return false;
-                }
-            }
-        }
-        true
-    }
-
-    fn test_current_position(&self, game_map: &[Vec<u8>]) -> bool {
-        self.test_position(game_map, self.current_state, self.x, self.y)
-    }
-
-    fn change_position(&mut self, game_map: &[Vec<u8>], new_x: isize, new_y: usize) -> bool {
-        if self.test_position(game_map, self.current_state, new_x, new_y) {
-            self.x = new_x;
-            self.y = new_y;
-            return true;
-        }
-        false
-    }
-}
-
-#[derive(Clone, Copy, PartialEq)]
-enum TetriminoTypes {
-    TetriminoI,
-    TetriminoJ,
-    TetriminoL,
-    TetriminoO,
-    TetriminoS,
-    TetriminoT,
-    TetriminoZ,
-}
-
-impl Distribution<TetriminoTypes> for Standard {
-    fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> TetriminoTypes {
-        match rng.gen_range(0..=6) {
-            0 => TetriminoTypes::TetriminoI,
-            1 => TetriminoTypes::TetriminoJ,
-            2 => TetriminoTypes::TetriminoL,
-            3 => TetriminoTypes::TetriminoO,
-            4 => TetriminoTypes::TetriminoS,
-            5 => TetriminoTypes::TetriminoT,
-            _ => TetriminoTypes::TetriminoZ,
-        }
-    }
-}
-
-impl TetriminoTypes {
-    fn generate(self) -> Tetrimino {
-        match self {
-            Self::TetriminoI => Tetrimino {
-                states: vec![
-                    vec![
-                        vec![1, 1, 1, 1],
-                        vec![0, 0, 0, 0],
-                        vec![0, 0, 0, 0],
-                        vec![0, 0, 0, 0],
-                    ],
-                    vec![
-                        vec![0, 1, 0, 0],
-                        vec![0, 1, 0, 0],
-                        vec![0, 1, 0, 0],
-                        vec![0, 1, 0, 0],
-                    ],
-                ],
-                x: 4,
-                y: 0,
-                current_state: 0,
-            },
-            Self::TetriminoJ => Tetrimino {
-                states: vec![
-                    vec![
-                        vec![2, 2, 2, 0],
-                        vec![0, 0, 2, 0],
-                        vec![0, 0, 0, 0],
-                        vec![0, 0, 0, 0],
-                    ],
-                    vec![
-                        vec![2, 2, 0, 0],
-                        vec![2, 0, 0, 0],
-                        vec![2, 0, 0, 0],
-                        vec![0, 0, 0, 0],
-                    ],
-                    vec![
-                        vec![2, 0, 0, 0],
-                        vec![2, 2, 2, 0],
-                        vec![0, 0, 0, 0],
-                        vec![0, 0, 0, 0],
-                    ],
-                    vec![
-                        vec![0, 2, 0, 0],
-                        vec![0, 2, 0, 0],
-                        vec![2, 2, 0, 0],
-                        vec![0, 0, 0, 0],
-                    ],
-                ],
-                x: 4,
-                y: 0,
-                current_state: 0,
-            },
-            Self::TetriminoL => Tetrimino {
-                states: vec![
-                    vec![
-                        vec![3, 3, 3, 0],
-                        vec![3, 0, 0, 0],
-                        vec![0, 0, 0, 0],
-                        vec![0, 0, 0, 0],
-                    ],
-                    vec![
-                        vec![3, 3, 0, 0],
-                        vec![0, 3, 0, 0],
-                        vec![0, 3, 0, 0],
-                        vec![0, 0, 0, 0],
-                    ],
-                    vec![
-                        vec![0, 0, 3, 0],
-                        vec![3, 3, 3, 0],
-                        vec![0, 0, 0, 0],
-                        vec![0, 0, 0, 0],
-                    ],
-                    vec![
-                        vec![3, 0, 0, 0],
-                        vec![3, 0, 0, 0],
-                        vec![3, 3, 0, 0],
-                        vec![0, 0, 0, 0],
-                    ],
-                ],
-                x: 4,
-                y: 0,
-                current_state: 0,
-            },
-            Self::TetriminoO => Tetrimino {
-                states: vec![vec![
-                    vec![4, 4, 0, 0],
-                    vec![4, 4, 0, 0],
-                    vec![0, 0, 0, 0],
-                    vec![0, 0, 0, 0],
-                ]],
-                x: 5,
-                y: 0,
-                current_state: 0,
-            },
-            Self::TetriminoS => Tetrimino {
-                states: vec![
-                    vec![
-                        vec![0, 5, 5, 0],
-                        vec![5, 5, 0, 0],
-                        vec![0, 0, 0, 0],
-                        vec![0, 0, 0, 0],
-                    ],
-                    vec![
-                        vec![0, 5, 0, 0],
-                        vec![0, 5, 5, 0],
-                        vec![0, 0, 5, 0],
-                        vec![0, 0, 0, 0],
-                    ],
-                ],
-                x: 4,
-                y: 0,
-                current_state: 0,
-            },
-            Self::TetriminoT => Tetrimino {
-                states: vec![
-                    vec![
-                        vec![6, 6, 6, 0],
-                        vec![0, 6, 0, 0],
-                        vec![0, 0, 0, 0],
-                        vec![0, 0, 0, 0],
-                    ],
-                    vec![
-                        vec![0, 6, 0, 0],
-                        vec![6, 6, 0, 0],
-                        vec![0, 6, 0, 0],
-                        vec![0, 0, 0, 0],
-                    ],
-                    vec![
-                        vec![0, 6, 0, 0],
-                        vec![6, 6, 6, 0],
-                        vec![0, 0, 0, 0],
-                        vec![0, 0, 0, 0],
-                    ],
-                    vec![
-                        vec![0, 6, 0, 0],
-                        vec![0, 6, 6, 0],
-                        vec![0, 6, 0, 0],
-                        vec![0, 0, 0, 0],
-                    ],
-                ],
-                x: 4,
-                y: 0,
-                current_state: 0,
-            },
-            Self::TetriminoZ => Tetrimino {
-                states: vec![
-                    vec![
-                        vec![7, 7, 0, 0],
-                        vec![0, 7, 7, 0],
-                        vec![0, 0, 0, 0],
-                        vec![0, 0, 0, 0],
-                    ],
-                    vec![
-                        vec![0, 0, 7, 0],
-                        vec![0, 7, 7, 0],
-                        vec![0, 7, 0, 0],
-                        vec![0, 0, 0, 0],
-                    ],
-                ],
-                x: 4,
-                y: 0,
-                current_state: 0,
-            },
-        }
-    }
-}
-
-struct Tetris {
-    game_map: Vec<Vec<u8>>,
-    current_level: u32,
-    score: u32,
-    nb_lines: u32,
-    current_piece: Option<Tetrimino>,
-    last_piece: Option<TetriminoTypes>,
-}
-
-impl Tetris {
-    fn new() -> Tetris {
-        let mut game_map: Vec<Vec<u8>> = Vec::new();
-        for _ in 0..16 {
-            game_map.push(Vec::from([0; 10]));
-        }
-        Tetris {
-            game_map,
-            current_level: 1,
-            score: 0,
-            nb_lines: 0,
-            current_piece: None,
-            last_piece: None,
-        }
-    }
-
-    fn create_next_tetrimino(&mut self) {
-        let mut next: TetriminoTypes = rand::random();
-        if let Some(last) = self.last_piece {
-            while next == last {
-                next = rand::random();
+fn hud_text(tetris: &Tetris) -> String {
+    match tetris.rules.mode {
+        Mode::Endless => format!(
+            "Tetris - Level {} - Score {} - Lines {}",
+            tetris.current_level, tetris.score, tetris.nb_lines
+        ),
+        Mode::Marathon(goal) => format!(
+            "Tetris - Marathon - Level {} - Score {} - {}/{} lines",
+            tetris.current_level,
+            tetris.score,
+            tetris.nb_lines.min(goal),
+            goal
+        ),
+        Mode::Sprint(goal) => format!(
+            "Tetris - Sprint - {}/{} lines - {}",
+            tetris.nb_lines.min(goal),
+            goal,
+            format_time(tetris.elapsed_millis())
+        ),
+        Mode::Ultra(minutes) => format!(
+            "Tetris - Ultra - Score {} - {} left",
+            tetris.score,
+            format_time(ultra_time_left(tetris, minutes))
+        ),
+        Mode::Dig(goal) => format!(
+            "Tetris - Dig - {}/{} garbage lines - {}",
+            tetris.garbage_cleared.min(goal),
+            goal,
+            format_time(tetris.elapsed_millis())
+        ),
+        Mode::Master => format!(
+            "Tetris - Master - Level {}/{} - Grade {} - {}",
+            tetris.current_level,
+            (tetris.current_level / 100 + 1) * 100,
+            grade(tetris),
+            format_time(tetris.elapsed_millis())
+        ),
+        Mode::Puzzle(goal) => format!(
+            "Tetris - Puzzle - {} - Next: {}",
+            goal.description(),
+            tetris
+                .queue
+                .iter()
+                .map(|piece| piece.to_char())
+                .collect::<String>()
+        ),
+        Mode::Versus | Mode::Coop => format!("Tetris - {}", player_text(tetris)),
+    }
+}
+
+fn player_text(tetris: &Tetris) -> String {
+    match tetris.rules.mode {
+        Mode::Versus => format!(
+            "Lines {} - Sent {} - Incoming {}{}",
+            tetris.nb_lines,
+            tetris.attack.sent,
+            tetris.attack.pending,
+            match tetris.attack.combo {
+                0 | 1 => String::new(),
+                combo => format!(" - Combo {}", combo - 1),
             }
-        }
-        self.last_piece = Some(next);
-        self.current_piece = Some(TetriminoTypes::generate(next));
+        ),
+        _ => format!(
+            "Level {} - Score {} - Lines {}",
+            tetris.current_level, tetris.score, tetris.nb_lines
+        ),
     }
+}
+
+fn players_hud_text(players: &[Tetris]) -> String {
+    let mode = match players[0].rules.mode {
+        Mode::Versus => "Versus",
+        _ => "Coop",
+    };
+    let players_text = players
+        .iter()
+        .enumerate()
+        .map(|(i, tetris)| format!("P{}: {}", i + 1, player_text(tetris)))
+        .collect::<Vec<_>>()
+        .join(" | ");
+    format!("Tetris - {} - {}", mode, players_text)
+}
 
-    fn update_score(&mut self, to_add: u32) {
-        self.score += to_add;
+fn rank_text(rank: Option<usize>) -> String {
+    match rank {
+        Some(1) => " [PERSONAL BEST]".to_string(),
+        Some(rank) => format!(" [#{}]", rank),
+        None => "".to_string(),
     }
+}
 
-    fn check_lines(&mut self) {
-        let mut y = 0;
-        let mut score_add = 0;
+fn print_marathon_info(tetris: &Tetris, goal: u32) {
+    let rank = score::save_marathon_score(goal, tetris.score);
 
-        while y < self.game_map.len() {
-            let mut complete = true;
+    if tetris.is_complete() {
+        println!("Marathon complete!");
+    } else {
+        println!("Marathon failed at {}/{} lines.", tetris.nb_lines, goal);
+    }
+    println!("Score:            {}{}", tetris.score, rank_text(rank));
+    println!("Number of lines:  {}", tetris.nb_lines);
+    println!("Current level:    {}", tetris.current_level);
+    println!("Best scores:");
+    for (i, best) in score::load_marathon_scores(goal).iter().enumerate() {
+        println!("  {}. {}", i + 1, best);
+    }
+}
 
-            for x in &self.game_map[y] {
-                if x == &0 {
-                    complete = false;
-                    break;
-                }
-            }
-            if complete {
-                score_add += self.current_level;
-                self.game_map.remove(y);
-            } else {
-                y += 1;
-            }
-        }
-        if self.game_map.is_empty() {
-            // A "tetris"
-            score_add += 1000;
-        }
-        self.update_score(score_add);
+fn print_sprint_info(tetris: &Tetris, goal: u32) {
+    if !tetris.is_complete() {
+        println!("Sprint failed at {}/{} lines.", tetris.nb_lines, goal);
+        return;
+    }
+    let time = *tetris.splits.last().unwrap_or(&tetris.elapsed_millis());
+    let rank = score::save_sprint_time(goal, time);
 
-        while self.game_map.len() < 16 {
-            self.increase_line();
-            self.game_map.insert(0, Vec::from([0; 10]));
-        }
+    println!("Sprint complete!");
+    println!("Time:             {}{}", format_time(time), rank_text(rank));
+    for (i, split) in tetris.splits.iter().enumerate() {
+        let previous = if i == 0 { 0 } else { tetris.splits[i - 1] };
+        println!(
+            "Split {:>3} lines: {} (+{})",
+            (i + 1) * 10,
+            format_time(*split),
+            format_time(split - previous)
+        );
+    }
+    println!("Best times:");
+    for (i, best) in score::load_sprint_times(goal).iter().enumerate() {
+        println!("  {}. {}", i + 1, format_time(*best));
     }
+}
 
-    fn make_permanent(&mut self) {
-        let mut to_add = 0;
-        if let Some(ref mut piece) = self.current_piece {
-            let mut shift_y = 0;
+fn print_ultra_info(tetris: &Tetris, minutes: u32) {
+    if !tetris.is_complete() {
+        println!(
+            "Ultra failed with {} left.",
+            format_time(ultra_time_left(tetris, minutes))
+        );
+        return;
+    }
+    let rank = score::save_ultra_score(minutes, tetris.score);
 
-            while shift_y < piece.states[piece.current_state as usize].len()
-                && piece.y + shift_y < self.game_map.len()
-            {
-                let mut shift_x = 0;
+    println!("Time's up!");
+    println!("Score:            {}{}", tetris.score, rank_text(rank));
+    println!("Number of lines:  {}", tetris.nb_lines);
+    println!("Best scores:");
+    for (i, best) in score::load_ultra_scores(minutes).iter().enumerate() {
+        println!("  {}. {}", i + 1, best);
+    }
+}
 
-                while shift_x < piece.states[piece.current_state as usize][shift_y].len()
-                    && (piece.x + shift_x as isize)
-                        < self.game_map[piece.y + shift_y].len() as isize
-                {
-                    if piece.states[piece.current_state as usize][shift_y][shift_x] != 0 {
-                        let x = piece.x + shift_x as isize;
-                        self.game_map[piece.y + shift_y][x as usize] =
-                            piece.states[piece.current_state as usize][shift_y][shift_x];
-                    }
-                    shift_x += 1;
-                }
-                shift_y += 1;
-            }
-            to_add += self.current_level;
-        }
-        self.update_score(to_add);
-        self.check_lines();
-        self.current_piece = None;
+fn print_dig_info(tetris: &Tetris, goal: u32) {
+    if !tetris.is_complete() {
+        println!(
+            "Dig failed at {}/{} garbage lines.",
+            tetris.garbage_cleared, goal
+        );
+        return;
     }
+    let rank = score::save_dig_time(goal, tetris.elapsed_millis());
 
-    fn increase_line(&mut self) {
-        self.nb_lines += 1;
-        if self.nb_lines > LEVEL_LINES[self.current_level as usize - 1] {
-            self.current_level += 1;
-        }
+    println!("Dig complete!");
+    println!(
+        "Time:             {}{}",
+        format_time(tetris.elapsed_millis()),
+        rank_text(rank)
+    );
+    println!("Best times:");
+    for (i, best) in score::load_dig_times(goal).iter().enumerate() {
+        println!("  {}. {}", i + 1, format_time(*best));
     }
 }
 
-fn is_time_over(tetris: &Tetris, timer: &SystemTime) -> bool {
-    match timer.elapsed() {
-        Ok(elapsed) => {
-            let millis = elapsed.as_secs() as u32 * 1000 + elapsed.subsec_millis();
-            millis > LEVEL_TIMES[tetris.current_level as usize - 1]
-        }
-        Err(_) => false,
+fn print_master_info(tetris: &Tetris) {
+    if tetris.is_complete() {
+        println!("Master complete!");
+    } else {
+        println!("Game over at level {}.", tetris.current_level);
     }
+    println!("Grade:            {}", grade(tetris));
+    println!("Score:            {}", tetris.score);
+    println!("Number of lines:  {}", tetris.nb_lines);
 }
 
-fn handle_events(
-    tetris: &mut Tetris,
-    quit: &mut bool,
-    timer: &mut SystemTime,
-    event_pump: &mut sdl2::EventPump,
-) -> bool {
-    let mut make_permanant = false;
-    if let Some(ref mut piece) = tetris.current_piece {
-        let mut tmp_x = piece.x;
-        let mut tmp_y = piece.y;
-
-        for event in event_pump.poll_iter() {
-            match event {
-                Event::Quit { .. }
-                | Event::KeyDown {
-                    keycode: Some(Keycode::Escape),
-                    ..
-                } => {
-                    *quit = true;
-                    break;
-                }
-                Event::KeyDown {
-                    keycode: Some(Keycode::Down),
-                    ..
-                } => {
-                    *timer = SystemTime::now();
-                    tmp_y += 1;
-                }
-                Event::KeyDown {
-                    keycode: Some(Keycode::Right),
-                    ..
-                } => {
-                    tmp_x += 1;
-                }
-                Event::KeyDown {
-                    keycode: Some(Keycode::Left),
-                    ..
-                } => {
-                    tmp_x -= 1;
-                }
-                Event::KeyDown {
-                    keycode: Some(Keycode::Up),
-                    ..
-                } => {
-                    piece.rotate(&tetris.game_map);
-                }
-                Event::KeyDown {
-                    keycode: Some(Keycode::Space),
-                    ..
-                } => {
-                    let x = piece.x;
-                    let mut y = piece.y;
-                    while piece.change_position(&tetris.game_map, x, y + 1) {
-                        y += 1;
-                    }
-                }
-                _ => {}
-            }
-        }
-        if !make_permanant
-            && !piece.change_position(&tetris.game_map, tmp_x, tmp_y)
-            && tmp_y != piece.y
-        {
-            make_permanant = true;
-        }
+fn print_stats(tetris: &Tetris) {
+    let seconds = tetris.elapsed_millis() as f64 / 1000.;
+    // Completed races already printed their time along with its rank.
+    let race = matches!(tetris.rules.mode, Mode::Sprint(_) | Mode::Dig(_));
+    if !(race && tetris.is_complete()) {
+        println!("Time:             {}", format_time(tetris.elapsed_millis()));
     }
-    if make_permanant {
-        tetris.make_permanent();
-        *timer = SystemTime::now();
+    println!(
+        "Pieces:           {} ({:.2} per second)",
+        tetris.nb_pieces,
+        if seconds > 0. {
+            tetris.nb_pieces as f64 / seconds
+        } else {
+            0.
+        }
+    );
+    println!(
+        "Line clears:      {} singles, {} doubles, {} triples, {} tetrises",
+        tetris.nb_clears[0], tetris.nb_clears[1], tetris.nb_clears[2], tetris.nb_clears[3]
+    );
+    println!(
+        "T-spins:          {} singles, {} doubles, {} triples",
+        tetris.nb_tspins[0], tetris.nb_tspins[1], tetris.nb_tspins[2]
+    );
+    println!("Perfect clears:   {}", tetris.nb_perfect_clears);
+    println!("Finesse faults:   {}", tetris.finesse_faults);
+}
+
+fn print_puzzle_info(tetris: &Tetris, goal: Goal) {
+    if tetris.is_complete() {
+        println!("Puzzle solved!");
+    } else {
+        println!("Puzzle failed: {}.", goal.description().to_lowercase());
     }
-    make_permanant
+}
+
+fn print_versus_info(tetris: &Tetris) {
+    println!("Garbage sent:     {}", tetris.attack.sent);
+    println!("Garbage received: {}", tetris.garbage_added);
+    println!("Cancelled:        {}", tetris.attack.cancelled);
+    println!("Number of lines:  {}", tetris.nb_lines);
+}
+
+fn print_coop_info(tetris: &Tetris) {
+    println!("Score:            {}", tetris.score);
+    println!("Number of lines:  {}", tetris.nb_lines);
+    println!("Current level:    {}", tetris.current_level);
 }
 
 fn print_game_info(tetris: &Tetris) {
+    match tetris.rules.mode {
+        Mode::Endless => print_endless_info(tetris),
+        Mode::Marathon(goal) => print_marathon_info(tetris, goal),
+        Mode::Sprint(goal) => print_sprint_info(tetris, goal),
+        Mode::Ultra(minutes) => print_ultra_info(tetris, minutes),
+        Mode::Dig(goal) => print_dig_info(tetris, goal),
+        Mode::Master => print_master_info(tetris),
+        Mode::Puzzle(goal) => print_puzzle_info(tetris, goal),
+        Mode::Versus => print_versus_info(tetris),
+        Mode::Coop => print_coop_info(tetris),
+    }
+    print_stats(tetris);
+}
+
+fn print_endless_info(tetris: &Tetris) {
     let mut new_highest_highscore = true;
     let mut new_highest_lines_sent = true;
     if let Some((mut highscores, mut lines_sent)) = score::load_highscores_and_lines() {
@@ -592,21 +532,1020 @@ fn print_game_info(tetris: &Tetris) {
     println!("Current level:    {}", tetris.current_level);
 }
 
+struct Textures<'a> {
+    grid: Texture<'a>,
+    border: Texture<'a>,
+    // One texture per piece, and the garbage one last.
+    pieces: [Texture<'a>; 8],
+}
+
+// Draws a piece at half size, as in the preview and the hold box.
+fn draw_small_piece(
+    canvas: &mut Canvas<Window>,
+    textures: &Textures,
+    piece: TetriminoTypes,
+    x: i32,
+    y: i32,
+) {
+    let size = TETRIS_HEIGHT as i32 / 2;
+    for (case_nb, line_nb) in piece.states()[0].cells {
+        canvas
+            .copy(
+                &textures.pieces[piece.color() as usize - 1],
+                None,
+                Rect::new(
+                    x + case_nb as i32 * size,
+                    y + line_nb as i32 * size,
+                    size as u32,
+                    size as u32,
+                ),
+            )
+            .expect("Failed to copy tetrimino texture to window.");
+    }
+}
+
+fn draw_tetris(
+    canvas: &mut Canvas<Window>,
+    textures: &Textures,
+    tetris: &Tetris,
+    grid_x: i32,
+    grid_y: i32,
+) {
+    canvas
+        .copy(
+            &textures.border,
+            None,
+            Rect::new(
+                grid_x - 10,
+                grid_y - 10,
+                TETRIS_HEIGHT as u32 * 10 + 20,
+                TETRIS_HEIGHT as u32 * 16 + 20,
+            ),
+        )
+        .expect("Couldn't copy border texture into window.");
+
+    canvas
+        .copy(
+            &textures.grid,
+            None,
+            Rect::new(
+                grid_x,
+                grid_y,
+                TETRIS_HEIGHT as u32 * 10,
+                TETRIS_HEIGHT as u32 * 16,
+            ),
+        )
+        .expect("Couldn't copy grid texture into window.");
+
+    // The next pieces on the right of the board and the held one on its left.
+    let small = TETRIS_HEIGHT as i32 / 2;
+    let preview = tetris.rules.preview.min(PREVIEW_PIECES);
+    for (i, piece) in tetris.queue.iter().take(preview).enumerate() {
+        draw_small_piece(
+            canvas,
+            textures,
+            *piece,
+            grid_x + TETRIS_HEIGHT as i32 * 10 + 20,
+            grid_y + i as i32 * small * 3,
+        );
+    }
+    if let Some(piece) = tetris.hold_piece {
+        draw_small_piece(canvas, textures, piece, grid_x - 20 - small * 4, grid_y);
+    }
+
+    if let Some(piece) = tetris.current_piece {
+        for (case_nb, line_nb) in piece.cells() {
+            canvas
+                .copy(
+                    &textures.pieces[piece.kind.color() as usize - 1],
+                    None,
+                    Rect::new(
+                        grid_x + case_nb as i32 * TETRIS_HEIGHT as i32,
+                        grid_y + line_nb as i32 * TETRIS_HEIGHT as i32,
+                        TETRIS_HEIGHT as u32,
+                        TETRIS_HEIGHT as u32,
+                    ),
+                )
+                .expect("Failed to copy tetrimino texture to window.")
+        }
+    }
+
+    for (line_nb, line) in tetris.game_map.color_rows().iter().enumerate() {
+        for (case_nb, case) in line.iter().enumerate() {
+            if *case == 0 {
+                continue;
+            }
+            canvas
+                .copy(
+                    &textures.pieces[*case as usize - 1],
+                    None,
+                    Rect::new(
+                        grid_x + case_nb as i32 * TETRIS_HEIGHT as i32,
+                        grid_y + line_nb as i32 * TETRIS_HEIGHT as i32,
+                        TETRIS_HEIGHT as u32,
+                        TETRIS_HEIGHT as u32,
+                    ),
+                )
+                .expect("Failed to copy tetrimino texture to window.");
+        }
+    }
+}
+
+fn set_title(canvas: &mut Canvas<Window>, title: &str) {
+    if title != canvas.window().title() {
+        canvas
+            .window_mut()
+            .set_title(title)
+            .expect("Failed to set window title.");
+    }
+}
+
+fn result_text(tetris: &Tetris) -> String {
+    let outcome = match tetris.rules.mode {
+        Mode::Endless => "Game over".to_string(),
+        Mode::Marathon(_) if tetris.is_complete() => "Marathon complete!".to_string(),
+        Mode::Sprint(_) if tetris.is_complete() => format!(
+            "Sprint complete in {}",
+            format_time(*tetris.splits.last().unwrap_or(&0))
+        ),
+        Mode::Ultra(_) if tetris.is_complete() => "Time's up!".to_string(),
+        Mode::Dig(_) if tetris.is_complete() => {
+            format!("Dig complete in {}", format_time(tetris.elapsed_millis()))
+        }
+        _ => "Failed".to_string(),
+    };
+    format!(
+        "Tetris - {} - Score {} - Lines {} - Level {} - press Enter",
+        outcome, tetris.score, tetris.nb_lines, tetris.current_level
+    )
+}
+
+// Keeps the final boards on screen, dimmed, until a player confirms or quits.
+fn show_results(
+    canvas: &mut Canvas<Window>,
+    textures: &Textures,
+    boards: &[(&Tetris, i32)],
+    grid_y: i32,
+    title: &str,
+    event_pump: &mut sdl2::EventPump,
+    controls: &mut Controls,
+) {
+    set_title(canvas, title);
+    canvas.set_blend_mode(BlendMode::Blend);
+    loop {
+        let actions = controls.poll_actions(event_pump);
+        if actions.contains(&Action::Confirm) || actions.contains(&Action::Quit) {
+            break;
+        }
+
+        canvas.set_draw_color(Color::RGB(255, 0, 0));
+        canvas.clear();
+        for (tetris, grid_x) in boards {
+            draw_tetris(canvas, textures, tetris, *grid_x, grid_y);
+            canvas.set_draw_color(Color::RGBA(0, 0, 0, 160));
+            canvas
+                .fill_rect(Rect::new(
+                    *grid_x,
+                    grid_y,
+                    TETRIS_HEIGHT as u32 * 10,
+                    TETRIS_HEIGHT as u32 * 16,
+                ))
+                .expect("Failed to dim the grid.");
+        }
+        canvas.present();
+
+        sleep(Duration::new(0, 1_000_000_000u32 / 60));
+    }
+}
+
+fn save_replay(players: &[Tetris]) {
+    if let Some(replay) = Replay::record(players) {
+        match replay.save() {
+            Ok(path) => println!("Replay saved to {}", path.display()),
+            Err(e) => eprintln!("Failed to save the replay: {}", e),
+        }
+    }
+}
+
+// Starts streaming the games to the spectators of the broadcast, if any.
+fn stream(broadcast: &Option<Arc<Mutex<Broadcast>>>, players: &[Tetris]) -> Option<Stream> {
+    Stream::start(
+        Arc::clone(broadcast.as_ref()?),
+        players[0].rules,
+        players.iter().map(|tetris| tetris.seed).collect(),
+    )
+}
+
+// Runs a game until it ends or the player quits, then shows its results.
+// An agent plays the game instead of the player if there is one, and the
+// game is streamed to spectators if there is a stream.
+#[allow(clippy::too_many_arguments)]
+fn play(
+    canvas: &mut Canvas<Window>,
+    textures: &Textures,
+    tetris: &mut Tetris,
+    mut agent: Option<&mut dyn Agent>,
+    mut stream: Option<Stream>,
+    grid_x: i32,
+    grid_y: i32,
+    event_pump: &mut sdl2::EventPump,
+    controls: &mut Controls,
+) {
+    let mut last_frame = Instant::now();
+    let mut lag = Duration::ZERO;
+    let mut shown_splits = 0;
+
+    // main event loop
+    loop {
+        // The game runs at a fixed number of frames per second whatever the
+        // time spent drawing.
+        lag += last_frame.elapsed();
+        last_frame = Instant::now();
+        let frame = Duration::from_secs(1) / FRAMES_PER_SECOND;
+        while lag >= frame && !tetris.is_complete() && !tetris.game_over {
+            tetris.tick();
+            lag -= frame;
+        }
+        if let Some(ref mut stream) = stream {
+            stream.send_until(&[&tetris.inputs], tetris.frames);
+        }
+        if tetris.is_complete() || tetris.game_over {
+            break;
+        }
+
+        let mut quit = false;
+        handle_events(
+            tetris,
+            &mut quit,
+            agent.as_deref_mut(),
+            event_pump,
+            controls,
+        );
+        if quit {
+            break;
+        }
+
+        canvas.set_draw_color(Color::RGB(255, 0, 0));
+        canvas.clear();
+        draw_tetris(canvas, textures, tetris, grid_x, grid_y);
+
+        for split in &tetris.splits[shown_splits..] {
+            shown_splits += 1;
+            println!("{:>3} lines:  {}", shown_splits * 10, format_time(*split));
+        }
+
+        set_title(
+            canvas,
+            &format!(
+                "{} - Finesse faults {}",
+                hud_text(tetris),
+                tetris.finesse_faults
+            ),
+        );
+        canvas.present();
+
+        sleep(Duration::new(0, 1_000_000_000u32 / 60));
+    }
+
+    if let Some(stream) = stream {
+        stream.end(&[&tetris.inputs], tetris.frames);
+    }
+    print_game_info(tetris);
+    save_replay(std::slice::from_ref(tetris));
+    show_results(
+        canvas,
+        textures,
+        &[(tetris, grid_x)],
+        grid_y,
+        &result_text(tetris),
+        event_pump,
+        controls,
+    );
+}
+
+// Lets the AI play games one after the other, as an attract mode, until
+// Confirm or Quit is pressed. Nothing is saved.
+#[allow(clippy::too_many_arguments)]
+fn demo(
+    canvas: &mut Canvas<Window>,
+    textures: &Textures,
+    rules: Rules,
+    weights: Weights,
+    move_delay: u32,
+    grid_x: i32,
+    grid_y: i32,
+    event_pump: &mut sdl2::EventPump,
+    controls: &mut Controls,
+) {
+    let mut tetris = Tetris::new(rules);
+    let mut ai = Ai::new(weights, move_delay);
+    // Frames the last board stays on screen once a game ended.
+    let mut ended = 0;
+    let mut last_frame = Instant::now();
+    let mut lag = Duration::ZERO;
+
+    loop {
+        lag += last_frame.elapsed();
+        last_frame = Instant::now();
+        let frame = Duration::from_secs(1) / FRAMES_PER_SECOND;
+        while lag >= frame {
+            lag -= frame;
+            if tetris.game_over || tetris.is_complete() {
+                ended += 1;
+                if ended >= DEMO_RESTART_DELAY * FRAMES_PER_SECOND {
+                    tetris = Tetris::new(rules);
+                    ai = Ai::new(weights, move_delay);
+                    ended = 0;
+                }
+            } else {
+                tetris.tick();
+            }
+        }
+
+        let actions = controls.poll_actions(event_pump);
+        if actions.contains(&Action::Confirm) || actions.contains(&Action::Quit) {
+            return;
+        }
+        if !tetris.game_over && !tetris.is_complete() {
+            let ai_actions = ai.actions(&tetris);
+            apply_actions(&mut tetris, &ai_actions);
+        }
+
+        canvas.set_draw_color(Color::RGB(255, 0, 0));
+        canvas.clear();
+        draw_tetris(canvas, textures, &tetris, grid_x, grid_y);
+        set_title(
+            canvas,
+            &format!("{} - Demo, Enter or Escape to quit", hud_text(&tetris)),
+        );
+        canvas.present();
+
+        sleep(Duration::new(0, 1_000_000_000u32 / 60));
+    }
+}
+
+fn players_outcome(players: &[Tetris]) -> String {
+    match (players[0].game_over, players[1].game_over) {
+        (true, true) => "Draw".to_string(),
+        _ if players[0].rules.mode == Mode::Coop => format!(
+            "Team score {} - Lines {}",
+            players.iter().map(|tetris| tetris.score).sum::<u32>(),
+            players.iter().map(|tetris| tetris.nb_lines).sum::<u32>()
+        ),
+        (true, false) => "Player 2 wins!".to_string(),
+        (false, true) => "Player 1 wins!".to_string(),
+        (false, false) => "Game quit".to_string(),
+    }
+}
+
+// Runs a two player game side by side until one of the players reaches the
+// top or someone quits, then shows its results.
+// With an agent, the second player is the agent and the first one gets the
+// whole keyboard.
+#[allow(clippy::too_many_arguments)]
+fn play_players(
+    canvas: &mut Canvas<Window>,
+    textures: &Textures,
+    players: &mut [Tetris; 2],
+    mut agent: Option<&mut dyn Agent>,
+    mut stream: Option<Stream>,
+    grid_xs: [i32; 2],
+    grid_y: i32,
+    event_pump: &mut sdl2::EventPump,
+    controls: &mut Controls,
+) {
+    let mut last_frame = Instant::now();
+    let mut lag = Duration::ZERO;
+
+    loop {
+        lag += last_frame.elapsed();
+        last_frame = Instant::now();
+        let frame = Duration::from_secs(1) / FRAMES_PER_SECOND;
+        while lag >= frame && !is_over(players) {
+            for tetris in players.iter_mut() {
+                tetris.tick();
+            }
+            let [first, second] = players;
+            exchange_garbage(first, second);
+            lag -= frame;
+        }
+        if let Some(ref mut stream) = stream {
+            stream.send_until(&[&players[0].inputs, &players[1].inputs], players[0].frames);
+        }
+        if is_over(players) {
+            break;
+        }
+
+        let actions = controls.poll_player_actions(event_pump);
+        if actions.iter().any(|(_, action)| *action == Action::Quit) {
+            break;
+        }
+        for (player, tetris) in players.iter_mut().enumerate() {
+            let player_actions = match agent {
+                Some(ref mut agent) if player == 1 => agent.actions(tetris),
+                _ => actions
+                    .iter()
+                    .filter(|(p, _)| *p == player)
+                    .map(|(_, action)| *action)
+                    .collect::<Vec<_>>(),
+            };
+            apply_actions(tetris, &player_actions);
+        }
+        let [first, second] = players;
+        exchange_garbage(first, second);
+
+        canvas.set_draw_color(Color::RGB(255, 0, 0));
+        canvas.clear();
+        for (tetris, grid_x) in players.iter().zip(grid_xs) {
+            draw_tetris(canvas, textures, tetris, grid_x, grid_y);
+        }
+        set_title(canvas, &players_hud_text(players));
+        canvas.present();
+
+        sleep(Duration::new(0, 1_000_000_000u32 / 60));
+    }
+
+    if let Some(stream) = stream {
+        stream.end(&[&players[0].inputs, &players[1].inputs], players[0].frames);
+    }
+    show_players_results(
+        canvas, textures, players, grid_xs, grid_y, event_pump, controls,
+    );
+}
+
+fn show_players_results(
+    canvas: &mut Canvas<Window>,
+    textures: &Textures,
+    players: &[Tetris; 2],
+    grid_xs: [i32; 2],
+    grid_y: i32,
+    event_pump: &mut sdl2::EventPump,
+    controls: &mut Controls,
+) {
+    let outcome = players_outcome(players);
+    println!("{}", outcome);
+    for (i, tetris) in players.iter().enumerate() {
+        println!("\nPlayer {}", i + 1);
+        print_game_info(tetris);
+    }
+    save_replay(players);
+    show_results(
+        canvas,
+        textures,
+        &[(&players[0], grid_xs[0]), (&players[1], grid_xs[1])],
+        grid_y,
+        &format!("Tetris - {} - press Enter", outcome),
+        event_pump,
+        controls,
+    );
+}
+
+// Waits for the server to start the game, returning the index of the local
+// player, the seed of the games and the input delay, or None if the player
+// quits or the server leaves first.
+fn wait_for_start(
+    canvas: &mut Canvas<Window>,
+    connection: &mut Connection,
+    event_pump: &mut sdl2::EventPump,
+    controls: &mut Controls,
+) -> Option<(usize, u64, u32)> {
+    set_title(canvas, "Tetris - Waiting for an opponent...");
+    loop {
+        if controls.poll_actions(event_pump).contains(&Action::Quit) {
+            return None;
+        }
+        match connection.try_recv() {
+            Some(Message::Start {
+                player,
+                seed,
+                delay,
+            }) => return Some((player.min(1), seed, delay)),
+            Some(Message::Quit) => {
+                eprintln!("The server closed the connection.");
+                return None;
+            }
+            _ => {}
+        }
+
+        canvas.set_draw_color(Color::RGB(255, 0, 0));
+        canvas.clear();
+        canvas.present();
+
+        sleep(Duration::new(0, 1_000_000_000u32 / 60));
+    }
+}
+
+// Plays a versus game over the network. Both clients play both games: the
+// actions of the local player are sent `delay` frames ahead of the frame they
+// are played at, and the games go on up to `max_rollback` frames ahead of the
+// actions of the opponent, see the rollback module.
+#[allow(clippy::too_many_arguments)]
+fn play_online(
+    canvas: &mut Canvas<Window>,
+    textures: &Textures,
+    connection: &mut Connection,
+    max_rollback: u32,
+    grid_xs: [i32; 2],
+    grid_y: i32,
+    event_pump: &mut sdl2::EventPump,
+    controls: &mut Controls,
+) {
+    let (me, seed, delay) = match wait_for_start(canvas, connection, event_pump, controls) {
+        Some(start) => start,
+        None => return,
+    };
+    // Both games must be played with the same rules on both sides.
+    let rules = Rules {
+        mode: Mode::Versus,
+        finesse: Finesse::Count,
+        ..Rules::default()
+    };
+    let mut game = Rollback::new(
+        [
+            Tetris::with_seed(rules, seed),
+            Tetris::with_seed(rules, seed.wrapping_add(1)),
+        ],
+        me,
+        max_rollback,
+    );
+    // Nobody acts in the frames played before the first actions sent arrive.
+    for frame in 0..delay {
+        game.set_input(0, frame, Vec::new());
+        game.set_input(1, frame, Vec::new());
+    }
+    let mut pending = Vec::new();
+    let mut next_frame = delay;
+    let mut last_frame = Instant::now();
+    let mut lag = Duration::ZERO;
+    let mut opponent_left = false;
+
+    loop {
+        let actions = controls.poll_actions(event_pump);
+        if actions.contains(&Action::Quit) {
+            let _ = connection.send(&Message::Quit);
+            break;
+        }
+        pending.extend(actions);
+
+        // The local actions are sent at the pace of the game, but no further
+        // ahead of the opponent than the games can be played.
+        lag += last_frame.elapsed();
+        last_frame = Instant::now();
+        let frame = Duration::from_secs(1) / FRAMES_PER_SECOND;
+        while lag >= frame {
+            lag -= frame;
+            if next_frame > game.confirmed_frame() + delay + max_rollback {
+                continue;
+            }
+            let sent = std::mem::take(&mut pending);
+            let message = Message::Input {
+                frame: next_frame,
+                actions: encode_actions(&sent),
+            };
+            if connection.send(&message).is_err() {
+                opponent_left = true;
+            }
+            game.set_input(me, next_frame, sent);
+            next_frame += 1;
+        }
+
+        while let Some(message) = connection.try_recv() {
+            match message {
+                Message::Input { frame, actions } => {
+                    game.set_input(1 - me, frame, decode_actions(&actions))
+                }
+                Message::Quit => opponent_left = true,
+                Message::Start { .. } | Message::Game { .. } | Message::Played { .. } => {}
+            }
+        }
+
+        game.update();
+        if is_over(game.confirmed()) {
+            break;
+        }
+        if opponent_left {
+            println!("The opponent left the game.");
+            break;
+        }
+
+        canvas.set_draw_color(Color::RGB(255, 0, 0));
+        canvas.clear();
+        for (tetris, grid_x) in game.current().iter().zip(grid_xs) {
+            draw_tetris(canvas, textures, tetris, grid_x, grid_y);
+        }
+        set_title(
+            canvas,
+            &format!(
+                "{} - You are P{} - {} frames ahead",
+                players_hud_text(game.current()),
+                me + 1,
+                game.frame() - game.confirmed_frame()
+            ),
+        );
+        canvas.present();
+
+        sleep(Duration::new(0, 1_000_000_000u32 / 60));
+    }
+
+    println!(
+        "Rollbacks:        {} ({} frames played again)",
+        game.nb_rollbacks, game.resimulated_frames
+    );
+    show_players_results(
+        canvas,
+        textures,
+        &game.confirmed_games(),
+        grid_xs,
+        grid_y,
+        event_pump,
+        controls,
+    );
+}
+
+// Shows a game as it is played, from the actions of its players streamed by
+// the server, a local game or a replay. The games are shown `delay` frames
+// behind the last frame whose actions are known, so that they play smoothly,
+// and played faster to catch up when further behind, as after joining a game
+// in progress. A game of a single board is shown in the middle of the window.
+#[allow(clippy::too_many_arguments)]
+fn spectate(
+    canvas: &mut Canvas<Window>,
+    textures: &Textures,
+    connection: &mut Connection,
+    delay: u32,
+    grid_xs: [i32; 2],
+    grid_y: i32,
+    event_pump: &mut sdl2::EventPump,
+    controls: &mut Controls,
+) {
+    let finished = |players: &[Tetris]| {
+        players
+            .iter()
+            .any(|tetris| tetris.game_over || tetris.is_complete())
+    };
+    let mut players: Vec<Tetris> = Vec::new();
+    let mut inputs: Vec<VecDeque<Vec<Action>>> = Vec::new();
+    let mut ended = false;
+    let mut last_frame = Instant::now();
+    let mut lag = Duration::ZERO;
+    set_title(canvas, "Tetris - Waiting for a game to watch...");
+
+    loop {
+        if controls.poll_actions(event_pump).contains(&Action::Quit) {
+            return;
+        }
+        while let Some(message) = connection.try_recv() {
+            match message {
+                Message::Game {
+                    delay: input_delay,
+                    seeds,
+                    rules,
+                } => {
+                    players = seeds
+                        .iter()
+                        .map(|seed| Tetris::with_seed(rules, *seed))
+                        .collect();
+                    // Nobody acts in the frames before the first actions sent.
+                    inputs = seeds
+                        .iter()
+                        .map(|_| (0..input_delay).map(|_| Vec::new()).collect())
+                        .collect();
+                }
+                Message::Played {
+                    player, actions, ..
+                } => {
+                    if let Some(input) = inputs.get_mut(player) {
+                        input.push_back(decode_actions(&actions));
+                    }
+                }
+                Message::Quit => ended = true,
+                Message::Start { .. } | Message::Input { .. } => {}
+            }
+        }
+
+        if players.is_empty() {
+            if ended {
+                eprintln!("The server closed the connection.");
+                return;
+            }
+            canvas.set_draw_color(Color::RGB(255, 0, 0));
+            canvas.clear();
+            canvas.present();
+            sleep(Duration::new(0, 1_000_000_000u32 / 60));
+            continue;
+        }
+
+        lag += last_frame.elapsed();
+        last_frame = Instant::now();
+        let frame = Duration::from_secs(1) / FRAMES_PER_SECOND;
+        let mut steps = 0;
+        while lag >= frame {
+            lag -= frame;
+            steps += 1;
+        }
+        let known = inputs.iter().map(|input| input.len()).min().unwrap_or(0) as u32;
+        // Once the game is over, the frames left are played out without delay.
+        let buffered = if ended { 0 } else { delay };
+        if known > buffered + FRAMES_PER_SECOND {
+            steps = known - buffered;
+        }
+        for _ in 0..steps.min(known.saturating_sub(buffered)) {
+            for (tetris, input) in players.iter_mut().zip(inputs.iter_mut()) {
+                apply_actions(tetris, &input.pop_front().unwrap_or_default());
+            }
+            if let [first, second] = players.as_mut_slice() {
+                exchange_garbage(first, second);
+            }
+            if finished(&players) {
+                break;
+            }
+            for tetris in players.iter_mut() {
+                tetris.tick();
+            }
+            if let [first, second] = players.as_mut_slice() {
+                exchange_garbage(first, second);
+            }
+        }
+        if finished(&players) || (ended && known == 0) {
+            break;
+        }
+
+        canvas.set_draw_color(Color::RGB(255, 0, 0));
+        canvas.clear();
+        match players.as_slice() {
+            [tetris] => {
+                draw_tetris(
+                    canvas,
+                    textures,
+                    tetris,
+                    (grid_xs[0] + grid_xs[1]) / 2,
+                    grid_y,
+                );
+                set_title(canvas, &format!("{} - Spectating", hud_text(tetris)));
+            }
+            _ => {
+                for (tetris, grid_x) in players.iter().zip(grid_xs) {
+                    draw_tetris(canvas, textures, tetris, grid_x, grid_y);
+                }
+                set_title(
+                    canvas,
+                    &format!("{} - Spectating", players_hud_text(&players)),
+                );
+            }
+        }
+        canvas.present();
+
+        sleep(Duration::new(0, 1_000_000_000u32 / 60));
+    }
+
+    match players.as_slice() {
+        [tetris] => {
+            print_game_info(tetris);
+            save_replay(&players);
+            show_results(
+                canvas,
+                textures,
+                &[(tetris, (grid_xs[0] + grid_xs[1]) / 2)],
+                grid_y,
+                &result_text(tetris),
+                event_pump,
+                controls,
+            );
+        }
+        [first, second] => show_players_results(
+            canvas,
+            textures,
+            &[first.clone(), second.clone()],
+            grid_xs,
+            grid_y,
+            event_pump,
+            controls,
+        ),
+        _ => {}
+    }
+}
+
+const REPLAY_SPEEDS: [f64; 6] = [0.25, 0.5, 1., 2., 4., 8.];
+// Frames skipped by seeking forwards or backwards in a replay.
+const REPLAY_SEEK: u32 = 5 * FRAMES_PER_SECOND;
+
+// Plays a replay back. Hard drop pauses and resumes, rotate and soft drop
+// change the speed, left and right seek backwards and forwards, and confirm
+// plays a single frame while paused.
+fn watch_replay(
+    canvas: &mut Canvas<Window>,
+    textures: &Textures,
+    replay: &Replay,
+    grid_xs: &[i32],
+    grid_y: i32,
+    event_pump: &mut sdl2::EventPump,
+    controls: &mut Controls,
+) {
+    let mut playback = Playback::new(replay);
+    let mut speed = 2;
+    let mut paused = false;
+    let mut last_frame = Instant::now();
+    let mut lag = Duration::ZERO;
+
+    loop {
+        for action in controls.poll_actions(event_pump) {
+            match action {
+                Action::Quit => return,
+                Action::HardDrop => paused = !paused,
+                Action::Rotate => speed = (speed + 1).min(REPLAY_SPEEDS.len() - 1),
+                Action::SoftDrop => speed = speed.saturating_sub(1),
+                Action::MoveLeft => playback.seek(playback.frame().saturating_sub(REPLAY_SEEK)),
+                Action::MoveRight => playback.seek(playback.frame() + REPLAY_SEEK),
+                Action::Confirm => {
+                    paused = true;
+                    playback.step();
+                }
+                Action::Hold => {}
+            }
+        }
+
+        let elapsed = last_frame.elapsed();
+        last_frame = Instant::now();
+        if !paused {
+            lag += elapsed.mul_f64(REPLAY_SPEEDS[speed]);
+        }
+        let frame = Duration::from_secs(1) / FRAMES_PER_SECOND;
+        while lag >= frame {
+            lag -= frame;
+            playback.step();
+        }
+
+        canvas.set_draw_color(Color::RGB(255, 0, 0));
+        canvas.clear();
+        for (tetris, grid_x) in playback.players().iter().zip(grid_xs) {
+            draw_tetris(canvas, textures, tetris, *grid_x, grid_y);
+        }
+        let players = playback.players();
+        set_title(
+            canvas,
+            &format!(
+                "{} - Replay {} / {} - {}x{}",
+                match players {
+                    [tetris] => hud_text(tetris),
+                    _ => players_hud_text(players),
+                },
+                format_time(playback.frame() * 1000 / FRAMES_PER_SECOND),
+                format_time(playback.last_frame() * 1000 / FRAMES_PER_SECOND),
+                REPLAY_SPEEDS[speed],
+                if paused {
+                    " - Paused"
+                } else if playback.is_finished() {
+                    " - End"
+                } else {
+                    ""
+                }
+            ),
+        );
+        canvas.present();
+
+        sleep(Duration::new(0, 1_000_000_000u32 / 60));
+    }
+}
+
+// Shows the puzzles one at a time, returning the index of the one chosen to
+// be played or None if the player quits.
+#[allow(clippy::too_many_arguments)]
+fn browse_puzzles(
+    canvas: &mut Canvas<Window>,
+    textures: &Textures,
+    puzzles: &[Puzzle],
+    mut selected: usize,
+    grid_x: i32,
+    grid_y: i32,
+    event_pump: &mut sdl2::EventPump,
+    controls: &mut Controls,
+) -> Option<usize> {
+    if puzzles.is_empty() {
+        eprintln!("No puzzles found in {}.", puzzle::PUZZLES_DIR_PATH);
+        return None;
+    }
+    loop {
+        for action in controls.poll_actions(event_pump) {
+            match action {
+                Action::MoveLeft => selected = (selected + puzzles.len() - 1) % puzzles.len(),
+                Action::MoveRight => selected = (selected + 1) % puzzles.len(),
+                Action::Confirm => return Some(selected),
+                Action::Quit => return None,
+                _ => {}
+            }
+        }
+
+        let puzzle = &puzzles[selected];
+        let preview = Tetris::from_puzzle(Rules::default(), puzzle);
+        canvas.set_draw_color(Color::RGB(255, 0, 0));
+        canvas.clear();
+        draw_tetris(canvas, textures, &preview, grid_x, grid_y);
+        set_title(
+            canvas,
+            &format!(
+                "Tetris - Puzzle {}/{}: {} - {} - Pieces: {} - Left/Right to browse, Enter to play",
+                selected + 1,
+                puzzles.len(),
+                puzzle.name,
+                puzzle.goal.description(),
+                puzzle
+                    .queue
+                    .iter()
+                    .map(|piece| piece.to_char())
+                    .collect::<String>()
+            ),
+        );
+        canvas.present();
+
+        sleep(Duration::new(0, 1_000_000_000u32 / 60));
+    }
+}
+
 fn main() {
+    let options = match options::parse() {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, options::USAGE);
+            process::exit(1);
+        }
+    };
+    let mut spectator = options.watch.as_ref().map(|addr| {
+        Connection::connect(addr).unwrap_or_else(|e| {
+            eprintln!("Failed to connect to {}: {}", addr, e);
+            process::exit(1);
+        })
+    });
+    let mut connection = options.connect.as_ref().map(|addr| {
+        let mut connection = Connection::connect(addr).unwrap_or_else(|e| {
+            eprintln!("Failed to connect to {}: {}", addr, e);
+            process::exit(1);
+        });
+        connection.set_conditions(options.conditions);
+        connection
+    });
+    let broadcast = options.broadcast.map(|port| {
+        let broadcast = Broadcast::listen(port).unwrap_or_else(|e| {
+            eprintln!("Failed to listen on port {}: {}", port, e);
+            process::exit(1);
+        });
+        println!("Spectators can watch the game on port {}.", port);
+        broadcast
+    });
+    let mut tbp = options.tbp.as_ref().map(|command| {
+        let bot = TbpBot::launch(command, options.ai_delay, false).unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            process::exit(1);
+        });
+        println!("Bot: {} {} by {}", bot.name, bot.version, bot.author);
+        bot
+    });
     let sdl_context = sdl2::init().expect("SDL initalizaton failed.");
     let video_subsystem = sdl_context
         .video()
         .expect("Failed to find SDL video subsystem.");
-    let width = 600;
+    let controller_subsystem = sdl_context
+        .game_controller()
+        .expect("Failed to find SDL game controller subsystem.");
+    let replay = options.replay.as_ref().map(|path| {
+        Replay::load(path).unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            process::exit(1);
+        })
+    });
+    let weights = match options.ai_weights {
+        Some(ref path) => Weights::load(path).unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            process::exit(1);
+        }),
+        None => Weights::default(),
+    };
+    let nb_players = match replay {
+        Some(ref replay) => replay.players.len(),
+        None => options.rules.mode.players(),
+    };
+    // Over the network or against a bot, the whole keyboard goes to the local
+    // player.
+    let local_players = if options.connect.is_some()
+        || options.watch.is_some()
+        || replay.is_some()
+        || options.ai
+        || options.demo
+        || tbp.is_some()
+    {
+        1
+    } else {
+        nb_players
+    };
+    let mut controls = Controls::new(Bindings::load(), controller_subsystem, local_players);
+    // Every player gets a side of the window of its own.
+    let player_width = 600;
+    let width = player_width * nb_players as u32;
     let height = 800;
 
-    let mut tetris = Tetris::new();
-    let mut timer = SystemTime::now();
-    // main event loop
     let mut event_pump = sdl_context
         .event_pump()
         .expect("Failed to get SDL event pump.");
-    let grid_x = (width - TETRIS_HEIGHT as u32 * 10) as i32 / 2;
+    let grid_x = (player_width - TETRIS_HEIGHT as u32 * 10) as i32 / 2;
     let grid_y = (height - TETRIS_HEIGHT as u32 * 16) as i32 / 2;
 
     let window = video_subsystem
@@ -662,128 +1601,164 @@ fn main() {
         };
     }
 
-    let textures = vec![
-        texture!(255, 69, 69),
-        texture!(255, 220, 69),
-        texture!(237, 150, 37),
-        texture!(171, 99, 237),
-        texture!(77, 149, 239),
-        texture!(39, 218, 225),
-        texture!(45, 216, 47),
-    ];
+    let textures = Textures {
+        grid,
+        border,
+        pieces: [
+            texture!(255, 69, 69),
+            texture!(255, 220, 69),
+            texture!(237, 150, 37),
+            texture!(171, 99, 237),
+            texture!(77, 149, 239),
+            texture!(39, 218, 225),
+            texture!(45, 216, 47),
+            texture!(128, 128, 128),
+        ],
+    };
 
-    loop {
-        if is_time_over(&tetris, &timer) {
-            let mut make_permanent = false;
-            if let Some(ref mut piece) = tetris.current_piece {
-                let x = piece.x;
-                let y = piece.y + 1;
-                make_permanent = !piece.change_position(&tetris.game_map, x, y);
-            }
-            if make_permanent {
-                tetris.make_permanent();
-            }
-            timer = SystemTime::now();
-        }
-
-        canvas.set_draw_color(Color::RGB(255, 0, 0));
-        canvas.clear();
-
-        canvas
-            .copy(
-                &border,
-                None,
-                Rect::new(
-                    (width - TETRIS_HEIGHT as u32 * 10) as i32 / 2 - 10,
-                    (height - TETRIS_HEIGHT as u32 * 16) as i32 / 2 - 10,
-                    TETRIS_HEIGHT as u32 * 10 + 20,
-                    TETRIS_HEIGHT as u32 * 16 + 20,
-                ),
-            )
-            .expect("Couldn't copy border texture into window.");
-
-        canvas
-            .copy(
-                &grid,
+    if let Some(ref replay) = replay {
+        let grid_xs = (0..nb_players as i32)
+            .map(|i| grid_x + i * player_width as i32)
+            .collect::<Vec<_>>();
+        watch_replay(
+            &mut canvas,
+            &textures,
+            replay,
+            &grid_xs,
+            grid_y,
+            &mut event_pump,
+            &mut controls,
+        );
+        return;
+    }
+    if let Some(ref mut spectator) = spectator {
+        spectate(
+            &mut canvas,
+            &textures,
+            spectator,
+            options.watch_delay * FRAMES_PER_SECOND,
+            [grid_x, grid_x + player_width as i32],
+            grid_y,
+            &mut event_pump,
+            &mut controls,
+        );
+        return;
+    }
+    if let Some(ref mut connection) = connection {
+        play_online(
+            &mut canvas,
+            &textures,
+            connection,
+            options.rollback,
+            [grid_x, grid_x + player_width as i32],
+            grid_y,
+            &mut event_pump,
+            &mut controls,
+        );
+        return;
+    }
+    match options.puzzle {
+        Some(ref path) => {
+            let puzzle = puzzle::load(path).unwrap_or_else(|e| {
+                eprintln!("{}", e);
+                process::exit(1);
+            });
+            let mut tetris = Tetris::from_puzzle(options.rules, &puzzle);
+            play(
+                &mut canvas,
+                &textures,
+                &mut tetris,
+                tbp.as_mut().map(|bot| bot as &mut dyn Agent),
                 None,
-                Rect::new(
-                    (width - TETRIS_HEIGHT as u32 * 10) as i32 / 2,
-                    (height - TETRIS_HEIGHT as u32 * 16) as i32 / 2,
-                    TETRIS_HEIGHT as u32 * 10,
-                    TETRIS_HEIGHT as u32 * 16,
-                ),
-            )
-            .expect("Couldn't copy grid texture into window.");
-
-        if tetris.current_piece.is_none() {
-            tetris.create_next_tetrimino();
-            if !tetris
-                .current_piece
-                .as_ref()
-                .unwrap()
-                .test_current_position(&tetris.game_map)
-            {
-                print_game_info(&tetris);
-                break;
-            }
+                grid_x,
+                grid_y,
+                &mut event_pump,
+                &mut controls,
+            );
         }
-
-        let mut quit = false;
-        if !handle_events(&mut tetris, &mut quit, &mut timer, &mut event_pump) {
-            if let Some(ref mut piece) = tetris.current_piece {
-                for (line_nb, line) in piece.states[piece.current_state as usize]
-                    .iter()
-                    .enumerate()
-                {
-                    for (case_nb, case) in line.iter().enumerate() {
-                        if *case == 0 {
-                            continue;
-                        }
-
-                        canvas
-                            .copy(
-                                &textures[*case as usize - 1],
-                                None,
-                                Rect::new(
-                                    grid_x
-                                        + (piece.x + case_nb as isize) as i32
-                                            * TETRIS_HEIGHT as i32,
-                                    grid_y + (piece.y + line_nb) as i32 * TETRIS_HEIGHT as i32,
-                                    TETRIS_HEIGHT as u32,
-                                    TETRIS_HEIGHT as u32,
-                                ),
-                            )
-                            .expect("Failed to copy tetrimino texture to window.")
-                    }
-                }
+        None if matches!(options.rules.mode, Mode::Puzzle(_)) => {
+            let puzzles = puzzle::load_all();
+            let mut selected = 0;
+            while let Some(index) = browse_puzzles(
+                &mut canvas,
+                &textures,
+                &puzzles,
+                selected,
+                grid_x,
+                grid_y,
+                &mut event_pump,
+                &mut controls,
+            ) {
+                selected = index;
+                let mut tetris = Tetris::from_puzzle(options.rules, &puzzles[index]);
+                play(
+                    &mut canvas,
+                    &textures,
+                    &mut tetris,
+                    tbp.as_mut().map(|bot| bot as &mut dyn Agent),
+                    None,
+                    grid_x,
+                    grid_y,
+                    &mut event_pump,
+                    &mut controls,
+                );
             }
         }
-
-        if quit {
-            print_game_info(&tetris);
-            break;
-        }
-        for (line_nb, line) in tetris.game_map.iter().enumerate() {
-            for (case_nb, case) in line.iter().enumerate() {
-                if *case == 0 {
-                    continue;
+        None if options.demo => demo(
+            &mut canvas,
+            &textures,
+            options.rules,
+            weights,
+            options.ai_delay,
+            grid_x,
+            grid_y,
+            &mut event_pump,
+            &mut controls,
+        ),
+        None if nb_players == 2 => {
+            // The faults of the second player aren't counted when a bot plays it.
+            let second = if tbp.is_some() || options.ai {
+                Rules {
+                    finesse: Finesse::Off,
+                    ..options.rules
                 }
-                canvas
-                    .copy(
-                        &textures[*case as usize - 1],
-                        None,
-                        Rect::new(
-                            grid_x + case_nb as i32 * TETRIS_HEIGHT as i32,
-                            grid_y + line_nb as i32 * TETRIS_HEIGHT as i32,
-                            TETRIS_HEIGHT as u32,
-                            TETRIS_HEIGHT as u32,
-                        ),
-                    )
-                    .expect("Failed to copy tetrimino texture to window.");
-            }
+            } else {
+                options.rules
+            };
+            let mut players = [Tetris::new(options.rules), Tetris::new(second)];
+            let mut ai = Ai::new(weights, options.ai_delay);
+            let agent: Option<&mut dyn Agent> = match tbp {
+                Some(ref mut bot) => Some(bot),
+                None if options.ai => Some(&mut ai),
+                None => None,
+            };
+            let stream = stream(&broadcast, &players);
+            play_players(
+                &mut canvas,
+                &textures,
+                &mut players,
+                agent,
+                stream,
+                [grid_x, grid_x + player_width as i32],
+                grid_y,
+                &mut event_pump,
+                &mut controls,
+            );
+        }
+        None => {
+            let mut tetris = Tetris::new(options.rules);
+            let stream = stream(&broadcast, std::slice::from_ref(&tetris));
+            play(
+                &mut canvas,
+                &textures,
+                &mut tetris,
+                tbp.as_mut().map(|bot| bot as &mut dyn Agent),
+                stream,
+                grid_x,
+                grid_y,
+                &mut event_pump,
+                &mut controls,
+            );
         }
-        canvas.present();
-
-        sleep(Duration::new(0, 1_000_000_000u32 / 60));
     }
 }
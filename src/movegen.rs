@@ -0,0 +1,225 @@
+/*
+ * This module finds every position a piece can lock in from where it is, with
+ * the shortest sequence of actions getting it there. It searches the moves of
+ * the piece breadth first, one action at a time: the moves to the sides, the
+ * soft drops and the rotations, pushed aside as Tetrimino::rotate does, so
+ * that the positions only reachable by sliding the piece under an overhang or
+ * spinning it into a hole are found too. A position is locked in with a hard
+ * drop, which doesn't move a piece already resting on the stack. The same
+ * search, counting the soft drops before anything else, gives the fewest
+ * shifts and rotations a piece needs to lock somewhere, for the finesse.
+ */
+use crate::board::Board;
+use crate::engine::{Action, Tetrimino};
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+// Columns a piece can be at, its 4x4 states sticking out of the map by up to
+// three columns on the left.
+const MIN_X: isize = -3;
+const NB_COLUMNS: usize = 13;
+// Cost of a soft drop when searching for the finesse, more than any number of
+// other moves: a piece only needs to be soft dropped to slide or spin it into
+// a spot a hard drop can't reach, not to push it aside with a rotation.
+const FINESSE_DROP_COST: u32 = 1000;
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Move {
+    // Where the piece locks, as in Tetrimino::x, y and rotation.
+    pub x: isize,
+    pub y: usize,
+    pub rotation: u8,
+    // Whether the last move of the piece is a rotation, which makes a T-spin
+    // with a T piece.
+    pub spin: bool,
+    // The actions from where the piece is, the last one being a hard drop.
+    pub actions: Vec<Action>,
+}
+
+impl Move {
+    // The piece at the position it locks in.
+    pub fn piece(&self, piece: &Tetrimino) -> Tetrimino {
+        Tetrimino {
+            x: self.x,
+            y: self.y,
+            rotation: self.rotation,
+            ..*piece
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+struct Position {
+    x: isize,
+    y: usize,
+    rotation: u8,
+    // Whether the last move was a rotation.
+    rotated: bool,
+}
+
+// Index of a position in the tables of the search.
+fn index(position: Position, rows: usize) -> usize {
+    (((position.x - MIN_X) as usize * rows + position.y) * 4 + position.rotation as usize) * 2
+        + position.rotated as usize
+}
+
+fn position(index: usize, rows: usize) -> Position {
+    Position {
+        x: (index / (rows * 8)) as isize + MIN_X,
+        y: index / 8 % rows,
+        rotation: (index / 2 % 4) as u8,
+        rotated: index % 2 == 1,
+    }
+}
+
+fn is_landed(game_map: &Board, piece: &Tetrimino, position: Position) -> bool {
+    !piece.test_position(game_map, position.rotation, position.x, position.y + 1)
+}
+
+fn path(previous: &[Option<(usize, Action)>], mut index: usize) -> Vec<Action> {
+    let mut actions = Vec::new();
+    while let Some((from, action)) = previous[index] {
+        actions.push(action);
+        index = from;
+    }
+    actions.reverse();
+    actions
+}
+
+// Every position the piece can lock in from where it is, each once with the
+// shortest actions getting there, and once more with a spin if it can also
+// be spun in. Empty if the piece doesn't fit where it is.
+pub fn reachable_moves(game_map: &Board, piece: &Tetrimino) -> Vec<Move> {
+    search(game_map, piece, 1)
+}
+
+// The search, each soft drop counting as drop_cost actions. The positions are
+// searched from the ones reached with the fewest actions, so that the first
+// path found to lock somewhere is the shortest one.
+fn search(game_map: &Board, piece: &Tetrimino, drop_cost: u32) -> Vec<Move> {
+    let rows = game_map.height();
+    let start = Position {
+        x: piece.x,
+        y: piece.y,
+        rotation: piece.rotation,
+        rotated: false,
+    };
+    if !piece.test_current_position(game_map) {
+        return Vec::new();
+    }
+    let size = NB_COLUMNS * rows * 4 * 2;
+    // How every position was reached with the fewest actions, None for the start.
+    let mut previous: Vec<Option<(usize, Action)>> = vec![None; size];
+    let mut costs = vec![u32::MAX; size];
+    let mut locked = vec![false; size];
+    let mut queue = BinaryHeap::from([Reverse((0, index(start, rows)))]);
+    costs[index(start, rows)] = 0;
+    let mut moves = Vec::new();
+
+    while let Some(Reverse((cost, from))) = queue.pop() {
+        if cost > costs[from] {
+            continue;
+        }
+        let position = position(from, rows);
+
+        // The hard drop locking the piece from there.
+        let mut landing = position;
+        while !is_landed(game_map, piece, landing) {
+            landing.y += 1;
+            landing.rotated = false;
+        }
+        let target = index(landing, rows);
+        if !locked[target] {
+            locked[target] = true;
+            let mut actions = path(&previous, from);
+            actions.push(Action::HardDrop);
+            moves.push(Move {
+                x: landing.x,
+                y: landing.y,
+                rotation: landing.rotation,
+                spin: landing.rotated,
+                actions,
+            });
+        }
+
+        let mut next = Vec::with_capacity(4);
+        for (decal_x, action) in [(-1, Action::MoveLeft), (1, Action::MoveRight)] {
+            let x = position.x + decal_x;
+            if piece.test_position(game_map, position.rotation, x, position.y) {
+                next.push((
+                    Position {
+                        x,
+                        rotated: false,
+                        ..position
+                    },
+                    action,
+                ));
+            }
+        }
+        if !is_landed(game_map, piece, position) {
+            next.push((
+                Position {
+                    y: position.y + 1,
+                    rotated: false,
+                    ..position
+                },
+                Action::SoftDrop,
+            ));
+        }
+        if let Some((rotation, x)) =
+            piece.rotated_position(game_map, position.rotation, position.x, position.y)
+        {
+            next.push((
+                Position {
+                    x,
+                    rotation,
+                    rotated: true,
+                    ..position
+                },
+                Action::Rotate,
+            ));
+        }
+        for (position, action) in next {
+            let to = index(position, rows);
+            let step = if action == Action::SoftDrop {
+                drop_cost
+            } else {
+                1
+            };
+            if cost + step < costs[to] {
+                costs[to] = cost + step;
+                previous[to] = Some((from, action));
+                queue.push(Reverse((cost + step, to)));
+            }
+        }
+    }
+    moves
+}
+
+// The fewest actions locking the piece where the given one is, whether spun
+// in or not, or None if it can't get there.
+pub fn shortest_actions(
+    game_map: &Board,
+    piece: &Tetrimino,
+    target: &Tetrimino,
+) -> Option<Vec<Action>> {
+    reachable_moves(game_map, piece)
+        .into_iter()
+        .filter(|m| m.x == target.x && m.y == target.y && m.rotation == target.rotation)
+        .map(|m| m.actions)
+        .min_by_key(|actions| actions.len())
+}
+
+// The fewest shifts and rotations locking the piece where the given one is,
+// with as few soft drops as possible, or None if it can't get there.
+pub fn finesse(game_map: &Board, piece: &Tetrimino, target: &Tetrimino) -> Option<u32> {
+    search(game_map, piece, FINESSE_DROP_COST)
+        .into_iter()
+        .filter(|m| m.x == target.x && m.y == target.y && m.rotation == target.rotation)
+        .map(|m| {
+            let drops = m.actions.iter().filter(|a| **a == Action::SoftDrop).count() as u32;
+            (drops, m.actions.len() as u32 - drops - 1)
+        })
+        .min()
+        .map(|(_, moves)| moves)
+}
@@ -0,0 +1,464 @@
+/*
+ * This module contains the protocol of network versus play. The server hosts
+ * a room of two players and relays what each sends to the other, the games
+ * themselves being played by both clients. Messages are plaintext lines sent
+ * over TCP:
+ *
+ *     start <player> <seed> <delay>
+ *     input <frame> <actions>
+ *     game <delay> <seeds> <messiness> <level> <gravity> <level-up> <finesse> <preview> <hold> <mode>
+ *     played <player> <frame> <actions>
+ *     quit
+ *
+ * The server sends "start" once both players are in, with the index of the
+ * player, the seed of the games and the input delay in frames. Clients then
+ * send the actions of every frame, "-" for none, and "quit" when they leave.
+ *
+ * Spectators connect to a port of their own and get the "game" being played,
+ * with the seeds of its boards separated by commas and its rules as in
+ * replays, then the actions of every player as "played" from the start of the
+ * game, and "quit" when it ends. A spectator too slow to keep up is let go
+ * rather than holding back the game. The same broadcast streams network games
+ * from the server, local games from the game itself and replays from the
+ * server.
+ *
+ * Latency and packet loss can be simulated on the messages received, to try
+ * network play over the loopback interface.
+ */
+use crate::engine::Action;
+use crate::rules::{Finesse, Gravity, LevelUp, Mode, Rules};
+use rand::Rng;
+use std::collections::VecDeque;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::ops::Range;
+use std::sync::mpsc::{self, Receiver, SyncSender, TryRecvError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+pub const DEFAULT_PORT: u16 = 7777;
+pub const DEFAULT_SPECTATOR_PORT: u16 = 7778;
+pub const DEFAULT_DELAY: u32 = 3;
+// Lines a spectator can be behind by before it is let go, 10 seconds of a
+// game of two players, and time a write to it can take.
+const SPECTATOR_BACKLOG: usize = 2 * 60 * 10;
+const SPECTATOR_TIMEOUT: Duration = Duration::from_secs(10);
+// Time TCP takes to send a lost packet again.
+const RETRANSMISSION_TIMEOUT: Duration = Duration::from_millis(200);
+
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub struct Conditions {
+    // Delay added to every message, and extra random delay up to the jitter.
+    pub latency: Duration,
+    pub jitter: Duration,
+    // Chance in percent of a message being lost. As over TCP, it still arrives
+    // after a retransmission timeout, holding back the ones sent after it.
+    pub loss: u32,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum Message {
+    Start {
+        player: usize,
+        seed: u64,
+        delay: u32,
+    },
+    Input {
+        frame: u32,
+        actions: String,
+    },
+    Game {
+        delay: u32,
+        seeds: Vec<u64>,
+        rules: Rules,
+    },
+    Played {
+        player: usize,
+        frame: u32,
+        actions: String,
+    },
+    Quit,
+}
+
+impl Message {
+    pub fn parse(line: &str) -> Option<Message> {
+        let words = line.split_whitespace().collect::<Vec<_>>();
+        match words.as_slice() {
+            ["start", player, seed, delay] => Some(Message::Start {
+                player: player.parse().ok()?,
+                seed: seed.parse().ok()?,
+                delay: delay.parse().ok()?,
+            }),
+            ["input", frame, actions] => Some(Message::Input {
+                frame: frame.parse().ok()?,
+                actions: actions.to_string(),
+            }),
+            ["game", delay, seeds, messiness, level, gravity, level_up, finesse, preview, hold, mode @ ..] => {
+                Some(Message::Game {
+                    delay: delay.parse().ok()?,
+                    seeds: seeds
+                        .split(',')
+                        .map(|seed| seed.parse().ok())
+                        .collect::<Option<_>>()?,
+                    rules: Rules {
+                        mode: Mode::from_words(mode)?,
+                        messiness: messiness.parse().ok()?,
+                        start_level: level.parse().ok()?,
+                        gravity: Gravity::from_name(gravity)?,
+                        level_up: LevelUp::from_name(level_up)?,
+                        finesse: Finesse::from_name(finesse)?,
+                        preview: preview.parse().ok()?,
+                        hold: on_off(hold)?,
+                    },
+                })
+            }
+            ["played", player, frame, actions] => Some(Message::Played {
+                player: player.parse().ok()?,
+                frame: frame.parse().ok()?,
+                actions: actions.to_string(),
+            }),
+            ["quit"] => Some(Message::Quit),
+            _ => None,
+        }
+    }
+
+    pub fn to_line(&self) -> String {
+        match self {
+            Message::Start {
+                player,
+                seed,
+                delay,
+            } => format!("start {} {} {}\n", player, seed, delay),
+            Message::Input { frame, actions } => format!("input {} {}\n", frame, actions),
+            Message::Game {
+                delay,
+                seeds,
+                rules,
+            } => format!(
+                "game {} {} {} {} {} {} {} {} {} {}\n",
+                delay,
+                seeds
+                    .iter()
+                    .map(|seed| seed.to_string())
+                    .collect::<Vec<_>>()
+                    .join(","),
+                rules.messiness,
+                rules.start_level,
+                rules.gravity.name(),
+                rules.level_up.name(),
+                rules.finesse.name(),
+                rules.preview,
+                if rules.hold { "on" } else { "off" },
+                rules.mode.to_words().unwrap_or_default()
+            ),
+            Message::Played {
+                player,
+                frame,
+                actions,
+            } => format!("played {} {} {}\n", player, frame, actions),
+            Message::Quit => "quit\n".to_string(),
+        }
+    }
+}
+
+fn on_off(word: &str) -> Option<bool> {
+    match word {
+        "on" => Some(true),
+        "off" => Some(false),
+        _ => None,
+    }
+}
+
+// Actions of a frame as sent over the network.
+pub fn encode_actions(actions: &[Action]) -> String {
+    let encoded = actions
+        .iter()
+        .filter_map(|action| action.to_char())
+        .collect::<String>();
+    if encoded.is_empty() {
+        "-".to_string()
+    } else {
+        encoded
+    }
+}
+
+pub fn decode_actions(encoded: &str) -> Vec<Action> {
+    encoded.chars().filter_map(Action::from_char).collect()
+}
+
+// A connection to the other end, read from a thread of its own so that
+// receiving never blocks the game.
+pub struct Connection {
+    stream: TcpStream,
+    // Messages along with the time they were received.
+    messages: Receiver<(Instant, Message)>,
+    conditions: Conditions,
+    // Messages held back by the simulated conditions, with the time they are due.
+    delayed: VecDeque<(Instant, Message)>,
+    closed: bool,
+}
+
+impl Connection {
+    pub fn connect(addr: impl ToSocketAddrs) -> io::Result<Connection> {
+        Connection::from_stream(TcpStream::connect(addr)?)
+    }
+
+    pub fn from_stream(stream: TcpStream) -> io::Result<Connection> {
+        stream.set_nodelay(true)?;
+        let reader = BufReader::new(stream.try_clone()?);
+        let (sender, messages) = mpsc::channel();
+        thread::spawn(move || {
+            for line in reader.lines() {
+                let message = match line {
+                    Ok(line) => Message::parse(&line),
+                    Err(_) => break,
+                };
+                if let Some(message) = message {
+                    if sender.send((Instant::now(), message)).is_err() {
+                        return;
+                    }
+                }
+            }
+            // A closed connection reads as the other end leaving.
+            let _ = sender.send((Instant::now(), Message::Quit));
+        });
+        Ok(Connection {
+            stream,
+            messages,
+            conditions: Conditions::default(),
+            delayed: VecDeque::new(),
+            closed: false,
+        })
+    }
+
+    pub fn set_conditions(&mut self, conditions: Conditions) {
+        self.conditions = conditions;
+    }
+
+    fn due_time(&self, received: Instant) -> Instant {
+        let mut rng = rand::thread_rng();
+        let conditions = self.conditions;
+        let mut due = received + conditions.latency;
+        if !conditions.jitter.is_zero() {
+            due += conditions.jitter.mul_f64(rng.gen());
+        }
+        if rng.gen_range(0..100) < conditions.loss {
+            due += RETRANSMISSION_TIMEOUT;
+        }
+        // Messages arrive in the order they were sent.
+        match self.delayed.back() {
+            Some((last, _)) => due.max(*last),
+            None => due,
+        }
+    }
+
+    pub fn send(&mut self, message: &Message) -> io::Result<()> {
+        self.stream.write_all(message.to_line().as_bytes())
+    }
+
+    // The next message received, if any.
+    pub fn try_recv(&mut self) -> Option<Message> {
+        loop {
+            match self.messages.try_recv() {
+                Ok((received, message)) => {
+                    let due = self.due_time(received);
+                    self.delayed.push_back((due, message));
+                }
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    self.closed = true;
+                    break;
+                }
+            }
+        }
+        match self.delayed.front() {
+            Some((due, _)) if *due <= Instant::now() => {
+                self.delayed.pop_front().map(|(_, message)| message)
+            }
+            None if self.closed => Some(Message::Quit),
+            _ => None,
+        }
+    }
+}
+
+// The actions of a player sent to the spectators, for the frames in the
+// range, the ones without any left out as in the input logs of the games.
+#[derive(Default)]
+struct PlayedLog {
+    frames: Range<u32>,
+    actions: Vec<(u32, String)>,
+}
+
+// The spectators of the current game, each one written to from a thread of
+// its own, and what they were sent so far for the ones joining later.
+#[derive(Default)]
+pub struct Broadcast {
+    spectators: Vec<SyncSender<String>>,
+    game: Option<Message>,
+    played: Vec<PlayedLog>,
+}
+
+impl Broadcast {
+    // Listens for spectators on the given port from a thread of its own.
+    pub fn listen(port: u16) -> io::Result<Arc<Mutex<Broadcast>>> {
+        let listener = TcpListener::bind(("0.0.0.0", port))?;
+        let broadcast = Arc::new(Mutex::new(Broadcast::default()));
+        let spectators = Arc::clone(&broadcast);
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let joined = stream.and_then(|stream| {
+                    stream.set_nodelay(true)?;
+                    stream.set_write_timeout(Some(SPECTATOR_TIMEOUT))?;
+                    Ok(stream)
+                });
+                match joined {
+                    Ok(spectator) => spectators.lock().unwrap().join(spectator),
+                    Err(e) => eprintln!("Failed to accept a spectator: {}", e),
+                }
+            }
+        });
+        Ok(broadcast)
+    }
+
+    // The messages sent so far, rebuilt from the game and the actions of its
+    // players.
+    fn history(&self) -> String {
+        let mut history = self.game.iter().map(Message::to_line).collect::<String>();
+        for (player, log) in self.played.iter().enumerate() {
+            for frame in log.frames.clone() {
+                let actions = match log
+                    .actions
+                    .binary_search_by_key(&frame, |(frame, _)| *frame)
+                {
+                    Ok(index) => log.actions[index].1.clone(),
+                    Err(_) => encode_actions(&[]),
+                };
+                history.push_str(
+                    &Message::Played {
+                        player,
+                        frame,
+                        actions,
+                    }
+                    .to_line(),
+                );
+            }
+        }
+        history
+    }
+
+    // The spectator is sent the game so far, then every message, by a thread
+    // which gives up once a write times out or the spectator is let go.
+    fn join(&mut self, mut spectator: TcpStream) {
+        let (sender, lines) = mpsc::sync_channel::<String>(SPECTATOR_BACKLOG);
+        if sender.try_send(self.history()).is_err() {
+            return;
+        }
+        thread::spawn(move || {
+            for line in lines {
+                if spectator.write_all(line.as_bytes()).is_err() {
+                    return;
+                }
+            }
+        });
+        self.spectators.push(sender);
+    }
+
+    pub fn nb_spectators(&self) -> usize {
+        self.spectators.len()
+    }
+
+    // Spectators too far behind, or gone, are let go rather than waited for.
+    pub fn send(&mut self, message: Message) {
+        let line = message.to_line();
+        self.spectators
+            .retain(|spectator| spectator.try_send(line.clone()).is_ok());
+        match message {
+            Message::Game { .. } => {
+                self.game = Some(message);
+                self.played.clear();
+            }
+            Message::Played {
+                player,
+                frame,
+                actions,
+            } => {
+                if self.played.len() <= player {
+                    self.played.resize_with(player + 1, PlayedLog::default);
+                }
+                let log = &mut self.played[player];
+                if log.frames.is_empty() {
+                    log.frames = frame..frame;
+                }
+                log.frames.end = frame + 1;
+                if actions != encode_actions(&[]) {
+                    log.actions.push((frame, actions));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // Tells the spectators the game is over and lets them go.
+    pub fn end(&mut self) {
+        for spectator in self.spectators.drain(..) {
+            let _ = spectator.try_send(Message::Quit.to_line());
+        }
+        self.game = None;
+        self.played.clear();
+    }
+}
+
+// Streams a game to the spectators of a broadcast from the input logs of its
+// boards, as kept by the games and replays.
+pub struct Stream {
+    broadcast: Arc<Mutex<Broadcast>>,
+    // The first frame whose actions were not sent yet.
+    frame: u32,
+}
+
+impl Stream {
+    // None for puzzles, which can't be played again from their rules.
+    pub fn start(
+        broadcast: Arc<Mutex<Broadcast>>,
+        rules: Rules,
+        seeds: Vec<u64>,
+    ) -> Option<Stream> {
+        rules.mode.to_words()?;
+        broadcast.lock().unwrap().send(Message::Game {
+            delay: 0,
+            seeds,
+            rules,
+        });
+        Some(Stream {
+            broadcast,
+            frame: 0,
+        })
+    }
+
+    // Sends the actions of every board for the frames before `frame`.
+    pub fn send_until(&mut self, inputs: &[&[(u32, Vec<Action>)]], frame: u32) {
+        let mut broadcast = self.broadcast.lock().unwrap();
+        for played in self.frame..frame {
+            for (player, inputs) in inputs.iter().enumerate() {
+                let actions = match inputs.binary_search_by_key(&played, |(frame, _)| *frame) {
+                    Ok(index) => &inputs[index].1[..],
+                    Err(_) => &[],
+                };
+                broadcast.send(Message::Played {
+                    player,
+                    frame: played,
+                    actions: encode_actions(actions),
+                });
+            }
+        }
+        self.frame = self.frame.max(frame);
+    }
+
+    // Sends the actions up to the last frame played, `frame` included, and
+    // ends the broadcast.
+    pub fn end(mut self, inputs: &[&[(u32, Vec<Action>)]], frame: u32) {
+        self.send_until(inputs, frame + 1);
+        self.broadcast.lock().unwrap().end();
+    }
+}
@@ -0,0 +1,254 @@
+/*
+ * This module parses the command line arguments into the game options.
+ */
+use std::env;
+use std::path::PathBuf;
+use std::time::Duration;
+use tetris::cli::{parse_preview, parse_value};
+use tetris::net::{self, Conditions};
+use tetris::puzzle::Goal;
+use tetris::rules::{Finesse, Gravity, LevelUp, Mode, Rules};
+
+pub const USAGE: &str = "Usage: tetris [OPTIONS]
+
+Options:
+    --mode <endless|marathon|sprint|ultra|dig|master|puzzle|versus|coop>
+                                              Game mode (default endless)
+    --lines <N>                               Line goal of a marathon (150 or 200, default 150),
+                                              a sprint (20, 40 or 100, default 40)
+                                              or a dig (10, 18 or 100, default 10)
+    --minutes <2|3|5>                         Time limit of an ultra (default 3)
+    --puzzle <FILE>                           Puzzle to play, instead of browsing them all
+    --connect <HOST[:PORT]>                   Play versus over the network through the server
+                                              at the given address (default port 7777)
+    --watch <HOST[:PORT]>                     Watch the network games of the server at the
+                                              given address (default port 7778)
+    --watch-delay <SECONDS>                   Delay of the games watched (default 3)
+    --broadcast <PORT>                        Let spectators watch the local game with --watch
+                                              on the given port
+    --replay <FILE>                           Play a replay back
+    --ai                                      Play versus against the AI
+    --demo                                    Watch the AI play, until Enter or Escape
+    --ai-delay <FRAMES>                       Frames between two moves of the AI (default 8)
+    --ai-weights <FILE>                       Weights of the AI evaluation
+    --tbp <COMMAND>                           Let an external bot play through the Tetris Bot
+                                              Protocol, as player 2 in two player modes
+    --rollback <N>                            Frames a network game can be played ahead of
+                                              the opponent's actions (default 8, 0 to wait)
+    --net-latency <MS>                        Latency to simulate on the network
+    --net-jitter <MS>                         Random extra latency to simulate on the network
+    --net-loss <0-100>                        Packet loss in percent to simulate on the network
+    --messiness <0-100>                       Chance in percent of a garbage hole moving
+                                              between two rows (default 30)
+    --level <N>                               Starting level (default 1)
+    --gravity <classic|guideline|nes>         Gravity curve (default classic)
+    --level-up <table|fixed|variable>         Level up rule (default table)
+    --finesse-training                        Pieces placed with more moves than needed start
+                                              again from the top
+    --preview <0-5>                           Number of next pieces shown (default 0)
+    --hold                                    Let the current piece be put aside";
+
+const MODES: [&str; 9] = [
+    "endless", "marathon", "sprint", "ultra", "dig", "master", "puzzle", "versus", "coop",
+];
+
+pub struct Options {
+    pub rules: Rules,
+    pub puzzle: Option<PathBuf>,
+    pub connect: Option<String>,
+    pub watch: Option<String>,
+    pub watch_delay: u32,
+    pub broadcast: Option<u16>,
+    pub replay: Option<PathBuf>,
+    pub rollback: u32,
+    pub conditions: Conditions,
+    pub ai: bool,
+    pub demo: bool,
+    pub ai_delay: u32,
+    pub ai_weights: Option<PathBuf>,
+    // Program and arguments of the external bot.
+    pub tbp: Option<Vec<String>>,
+}
+
+// The first of the allowed goals is the default one.
+fn line_goal(lines: Option<u32>, allowed: &[u32]) -> Result<u32, String> {
+    match lines {
+        Some(lines) if !allowed.contains(&lines) => {
+            Err(format!("Invalid value for --lines: {}", lines))
+        }
+        Some(lines) => Ok(lines),
+        None => Ok(allowed[0]),
+    }
+}
+
+// Adds the default port to an address given without one.
+fn address(addr: &str, port: u16) -> String {
+    if addr.contains(':') {
+        addr.to_string()
+    } else {
+        format!("{}:{}", addr, port)
+    }
+}
+
+pub fn parse() -> Result<Options, String> {
+    // People playing are shown their finesse faults.
+    let mut rules = Rules {
+        finesse: Finesse::Count,
+        ..Rules::default()
+    };
+    let mut mode = "endless".to_string();
+    let mut lines = None;
+    let mut minutes = 3;
+    let mut puzzle = None;
+    let mut connect = None;
+    let mut watch = None;
+    let mut watch_delay = 3;
+    let mut broadcast = None;
+    let mut replay = None;
+    let mut rollback = 8;
+    let mut conditions = Conditions::default();
+    let mut ai = false;
+    let mut demo = false;
+    let mut ai_delay = 8;
+    let mut ai_weights = None;
+    let mut tbp = None;
+    let mut args = env::args().skip(1);
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--mode" => {
+                mode = parse_value(args.next(), &arg, |v| {
+                    MODES.contains(&v).then(|| v.to_string())
+                })?
+            }
+            "--lines" => lines = Some(parse_value(args.next(), &arg, |v| v.parse::<u32>().ok())?),
+            "--minutes" => {
+                minutes = parse_value(args.next(), &arg, |v| {
+                    v.parse::<u32>().ok().filter(|m| [2, 3, 5].contains(m))
+                })?
+            }
+            "--puzzle" => {
+                puzzle = Some(parse_value(args.next(), &arg, |v| Some(PathBuf::from(v)))?);
+                mode = "puzzle".to_string();
+            }
+            "--connect" => {
+                connect = Some(parse_value(args.next(), &arg, |v| {
+                    Some(address(v, net::DEFAULT_PORT))
+                })?);
+                mode = "versus".to_string();
+            }
+            "--watch" => {
+                watch = Some(parse_value(args.next(), &arg, |v| {
+                    Some(address(v, net::DEFAULT_SPECTATOR_PORT))
+                })?);
+                mode = "versus".to_string();
+            }
+            "--replay" => {
+                replay = Some(parse_value(args.next(), &arg, |v| Some(PathBuf::from(v)))?)
+            }
+            "--ai" => {
+                ai = true;
+                mode = "versus".to_string();
+            }
+            "--demo" => demo = true,
+            "--ai-delay" => ai_delay = parse_value(args.next(), &arg, |v| v.parse().ok())?,
+            "--ai-weights" => {
+                ai_weights = Some(parse_value(args.next(), &arg, |v| Some(PathBuf::from(v)))?)
+            }
+            "--tbp" => {
+                tbp = Some(parse_value(args.next(), &arg, |v| {
+                    let command = v.split_whitespace().map(str::to_string).collect::<Vec<_>>();
+                    (!command.is_empty()).then_some(command)
+                })?)
+            }
+            "--broadcast" => broadcast = Some(parse_value(args.next(), &arg, |v| v.parse().ok())?),
+            "--watch-delay" => watch_delay = parse_value(args.next(), &arg, |v| v.parse().ok())?,
+            "--rollback" => rollback = parse_value(args.next(), &arg, |v| v.parse().ok())?,
+            "--net-latency" => {
+                conditions.latency = parse_value(args.next(), &arg, |v| {
+                    v.parse().ok().map(Duration::from_millis)
+                })?
+            }
+            "--net-jitter" => {
+                conditions.jitter = parse_value(args.next(), &arg, |v| {
+                    v.parse().ok().map(Duration::from_millis)
+                })?
+            }
+            "--net-loss" => {
+                conditions.loss = parse_value(args.next(), &arg, |v| {
+                    v.parse::<u32>().ok().filter(|loss| *loss <= 100)
+                })?
+            }
+            "--messiness" => {
+                rules.messiness = parse_value(args.next(), &arg, |v| {
+                    v.parse::<u32>().ok().filter(|m| *m <= 100)
+                })?
+            }
+            "--level" => {
+                rules.start_level = parse_value(args.next(), &arg, |v| {
+                    v.parse::<u32>().ok().filter(|level| *level > 0)
+                })?
+            }
+            "--gravity" => rules.gravity = parse_value(args.next(), &arg, Gravity::from_name)?,
+            "--level-up" => rules.level_up = parse_value(args.next(), &arg, LevelUp::from_name)?,
+            "--finesse-training" => rules.finesse = Finesse::Training,
+            "--preview" => rules.preview = parse_value(args.next(), &arg, parse_preview)?,
+            "--hold" => rules.hold = true,
+            _ => return Err(format!("Unknown option: {}", arg)),
+        }
+    }
+    rules.mode = match mode.as_str() {
+        "marathon" => Mode::Marathon(line_goal(lines, &[150, 200])?),
+        "sprint" => Mode::Sprint(line_goal(lines, &[40, 20, 100])?),
+        "ultra" => Mode::Ultra(minutes),
+        "dig" => Mode::Dig(line_goal(lines, &[10, 18, 100])?),
+        "master" => Mode::Master,
+        // The goal is replaced by the one of the puzzle played.
+        "puzzle" => Mode::Puzzle(Goal::Lines(0)),
+        "versus" => Mode::Versus,
+        "coop" => Mode::Coop,
+        _ => Mode::Endless,
+    };
+    if demo && (rules.mode.players() > 1 || matches!(rules.mode, Mode::Puzzle(_))) {
+        return Err("--demo only works with the single player modes except puzzles".to_string());
+    }
+    // A replay has the same rules for both players, so a bot can't play
+    // against a player in training, and bots aren't checked for finesse.
+    if rules.finesse == Finesse::Training && (tbp.is_some() || ai) {
+        return Err("--finesse-training can't be used with --ai or --tbp".to_string());
+    }
+    if demo || (tbp.is_some() && rules.mode.players() == 1) {
+        rules.finesse = Finesse::Off;
+    }
+    if broadcast.is_some()
+        && (connect.is_some()
+            || watch.is_some()
+            || replay.is_some()
+            || demo
+            || matches!(rules.mode, Mode::Puzzle(_)))
+    {
+        return Err(
+            "--broadcast can't be used with --connect, --watch, --replay, --demo or puzzles"
+                .to_string(),
+        );
+    }
+    if tbp.is_some() && (ai || demo) {
+        return Err("--tbp can't be used with --ai or --demo".to_string());
+    }
+    Ok(Options {
+        rules,
+        puzzle,
+        connect,
+        watch,
+        watch_delay,
+        broadcast,
+        replay,
+        rollback,
+        conditions,
+        ai,
+        demo,
+        ai_delay,
+        ai_weights,
+        tbp,
+    })
+}
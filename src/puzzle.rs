@@ -0,0 +1,143 @@
+/*
+ * This module contains the code to load puzzles from the plaintext files of
+ * the directory defined in PUZZLES_DIR_PATH. A puzzle file looks like:
+ *
+ *     name T-spin double
+ *     goal tspin-double
+ *     queue T
+ *     board
+ *     GG...GGGGG
+ *     GGG..GGGGG
+ *     GGGG.GGGGG
+ *
+ * The goal is one of "lines <N>", "perfect-clear" or "tspin-double" and the
+ * queue lists the pieces given, in order. The board rows are listed top to
+ * bottom and placed at the bottom of the map, with "." for an empty cell,
+ * a piece letter for a cell of that piece's colour and "G" for garbage.
+ */
+use crate::engine::{TetriminoTypes, GARBAGE};
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::Path;
+
+pub const PUZZLES_DIR_PATH: &str = "puzzles";
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Goal {
+    Lines(u32),
+    PerfectClear,
+    TSpinDouble,
+}
+
+impl Goal {
+    fn from_words(words: &[&str]) -> Option<Goal> {
+        match words {
+            ["lines", lines] => lines.parse::<u32>().ok().map(Goal::Lines),
+            ["perfect-clear"] => Some(Goal::PerfectClear),
+            ["tspin-double"] => Some(Goal::TSpinDouble),
+            _ => None,
+        }
+    }
+
+    pub fn description(self) -> String {
+        match self {
+            Goal::Lines(1) => "Clear 1 line".to_string(),
+            Goal::Lines(lines) => format!("Clear {} lines", lines),
+            Goal::PerfectClear => "Perfect clear".to_string(),
+            Goal::TSpinDouble => "T-spin double".to_string(),
+        }
+    }
+}
+
+pub struct Puzzle {
+    pub name: String,
+    pub goal: Goal,
+    pub queue: Vec<TetriminoTypes>,
+    pub board: Vec<Vec<u8>>,
+}
+
+fn parse_case(c: char) -> Option<u8> {
+    match c {
+        '.' => Some(0),
+        'G' => Some(GARBAGE),
+        c => TetriminoTypes::from_char(c).map(TetriminoTypes::color),
+    }
+}
+
+fn parse(content: &str) -> Result<Puzzle, String> {
+    let mut name = None;
+    let mut goal = None;
+    let mut queue = Vec::new();
+    let mut board = Vec::new();
+    let mut lines = content.lines().filter(|line| !line.trim().is_empty());
+
+    for line in lines.by_ref() {
+        let words = line.split_whitespace().collect::<Vec<_>>();
+        match words[0] {
+            "name" => name = Some(words[1..].join(" ")),
+            "goal" => {
+                goal = Some(Goal::from_words(&words[1..]).ok_or(format!("Invalid goal: {}", line))?)
+            }
+            "queue" => {
+                queue = words[1..]
+                    .concat()
+                    .chars()
+                    .map(|c| TetriminoTypes::from_char(c).ok_or(format!("Invalid piece: {}", c)))
+                    .collect::<Result<_, _>>()?
+            }
+            "board" => break,
+            _ => return Err(format!("Invalid line: {}", line)),
+        }
+    }
+    for line in lines {
+        let row = line
+            .trim()
+            .chars()
+            .map(|c| parse_case(c).ok_or(format!("Invalid cell: {}", c)))
+            .collect::<Result<Vec<_>, _>>()?;
+        if row.len() != 10 {
+            return Err(format!("Board rows must be 10 cells wide: {}", line));
+        }
+        board.push(row);
+    }
+    if board.len() > 16 {
+        return Err("Boards must be at most 16 rows high.".to_string());
+    }
+
+    Ok(Puzzle {
+        name: name.ok_or("Missing puzzle name.")?,
+        goal: goal.ok_or("Missing puzzle goal.")?,
+        queue,
+        board,
+    })
+}
+
+pub fn load(path: &Path) -> Result<Puzzle, String> {
+    let mut content = String::new();
+    File::open(path)
+        .and_then(|mut f| f.read_to_string(&mut content))
+        .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    parse(&content).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+// Loads every puzzle of PUZZLES_DIR_PATH, sorted by file name. Invalid puzzle
+// files are reported and skipped.
+pub fn load_all() -> Vec<Puzzle> {
+    let mut paths = match fs::read_dir(PUZZLES_DIR_PATH) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .collect::<Vec<_>>(),
+        Err(_) => Vec::new(),
+    };
+    paths.sort();
+    paths
+        .iter()
+        .filter_map(|path| match load(path) {
+            Ok(puzzle) => Some(puzzle),
+            Err(e) => {
+                eprintln!("{}", e);
+                None
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,411 @@
+/*
+ * This module saves games as replays in the directory defined in
+ * REPLAYS_DIR_PATH and plays them back. A game is played again the same way
+ * from the rules, the seed of every player and the actions they made by frame.
+ * A replay file looks like:
+ *
+ *     tetris-replay 2
+ *     mode sprint 40
+ *     messiness 30
+ *     level 1
+ *     gravity classic
+ *     level-up table
+ *     finesse count
+ *     preview 0
+ *     hold off
+ *     player 8123456789 1200 40 3 5230
+ *     12L 3LL 40H 9RRU 21H
+ *
+ * Every "player" line gives the seed of the game of a player and its final
+ * score, lines, level and number of frames, and is followed by the line of its
+ * actions: the number of frames since the previous actions, then the actions
+ * of the frame with the letters of Action::to_char.
+ */
+use crate::engine::{apply_actions, exchange_garbage, Action, Tetris};
+use crate::rules::{Finesse, Gravity, LevelUp, Mode, Rules};
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub const REPLAYS_DIR_PATH: &str = "replays";
+// The version changes whenever the same actions no longer give the same game,
+// such as when the order of the random draws changed.
+const HEADER: &str = "tetris-replay 2";
+// Frames between the states kept while playing a replay back, to seek
+// backwards without playing it again from the start.
+const SNAPSHOT_INTERVAL: u32 = 600;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Outcome {
+    pub score: u32,
+    pub lines: u32,
+    pub level: u32,
+    pub frames: u32,
+}
+
+impl Outcome {
+    pub fn of(tetris: &Tetris) -> Outcome {
+        Outcome {
+            score: tetris.score,
+            lines: tetris.nb_lines,
+            level: tetris.current_level,
+            frames: tetris.frames,
+        }
+    }
+}
+
+pub struct PlayerReplay {
+    pub seed: u64,
+    pub outcome: Outcome,
+    pub inputs: Vec<(u32, Vec<Action>)>,
+}
+
+pub struct Replay {
+    pub rules: Rules,
+    pub players: Vec<PlayerReplay>,
+}
+
+impl Replay {
+    // None for puzzles, which can't be played again from their rules.
+    pub fn record(players: &[Tetris]) -> Option<Replay> {
+        players[0].rules.mode.to_words()?;
+        Some(Replay {
+            rules: players[0].rules,
+            players: players
+                .iter()
+                .map(|tetris| PlayerReplay {
+                    seed: tetris.seed,
+                    outcome: Outcome::of(tetris),
+                    inputs: tetris.inputs.clone(),
+                })
+                .collect(),
+        })
+    }
+
+    pub fn to_text(&self) -> String {
+        let rules = self.rules;
+        let mut text = format!(
+            "{}\nmode {}\nmessiness {}\nlevel {}\ngravity {}\nlevel-up {}\nfinesse {}\npreview {}\nhold {}\n",
+            HEADER,
+            rules.mode.to_words().unwrap_or_default(),
+            rules.messiness,
+            rules.start_level,
+            rules.gravity.name(),
+            rules.level_up.name(),
+            rules.finesse.name(),
+            rules.preview,
+            if rules.hold { "on" } else { "off" }
+        );
+        for player in &self.players {
+            let outcome = player.outcome;
+            text += &format!(
+                "player {} {} {} {} {}\n",
+                player.seed, outcome.score, outcome.lines, outcome.level, outcome.frames
+            );
+            let mut last = 0;
+            let inputs = player
+                .inputs
+                .iter()
+                .map(|(frame, actions)| {
+                    let entry = format!(
+                        "{}{}",
+                        frame - last,
+                        actions
+                            .iter()
+                            .filter_map(|a| a.to_char())
+                            .collect::<String>()
+                    );
+                    last = *frame;
+                    entry
+                })
+                .collect::<Vec<_>>();
+            text += &inputs.join(" ");
+            text += "\n";
+        }
+        text
+    }
+
+    fn parse(content: &str) -> Result<Replay, String> {
+        let mut lines = content.lines();
+        match lines.next() {
+            Some(HEADER) => {}
+            Some(header) if header.starts_with("tetris-replay ") => {
+                return Err("Replay made by another version of the game.".to_string())
+            }
+            _ => return Err("Not a replay file.".to_string()),
+        }
+        let mut rules = Rules::default();
+        let mut players = Vec::new();
+        while let Some(line) = lines.next() {
+            let words = line.split_whitespace().collect::<Vec<_>>();
+            let invalid = || format!("Invalid line: {}", line);
+            let number = |word: &str| word.parse::<u32>().map_err(|_| invalid());
+            match words.as_slice() {
+                ["mode", mode @ ..] => rules.mode = Mode::from_words(mode).ok_or_else(invalid)?,
+                ["messiness", messiness] => rules.messiness = number(messiness)?,
+                ["level", level] => rules.start_level = number(level)?,
+                ["gravity", name] => {
+                    rules.gravity = Gravity::from_name(name).ok_or_else(invalid)?
+                }
+                ["level-up", name] => {
+                    rules.level_up = LevelUp::from_name(name).ok_or_else(invalid)?
+                }
+                ["finesse", name] => {
+                    rules.finesse = Finesse::from_name(name).ok_or_else(invalid)?
+                }
+                ["preview", preview] => rules.preview = number(preview)? as usize,
+                ["hold", "on"] => rules.hold = true,
+                ["hold", "off"] => rules.hold = false,
+                ["player", seed, score, nb_lines, level, frames] => {
+                    let outcome = Outcome {
+                        score: number(score)?,
+                        lines: number(nb_lines)?,
+                        level: number(level)?,
+                        frames: number(frames)?,
+                    };
+                    players.push(PlayerReplay {
+                        seed: seed.parse().map_err(|_| invalid())?,
+                        outcome,
+                        inputs: parse_inputs(lines.next().unwrap_or_default())?,
+                    });
+                }
+                _ => return Err(invalid()),
+            }
+        }
+        if players.len() != rules.mode.players() {
+            return Err("Wrong number of players for the mode.".to_string());
+        }
+        Ok(Replay { rules, players })
+    }
+
+    // Saves the replay in REPLAYS_DIR_PATH, named after the mode and the time.
+    pub fn save(&self) -> io::Result<PathBuf> {
+        fs::create_dir_all(REPLAYS_DIR_PATH)?;
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|time| time.as_secs())
+            .unwrap_or(0);
+        let mode = self.rules.mode.to_words().unwrap_or_default();
+        let path =
+            Path::new(REPLAYS_DIR_PATH).join(format!("{}-{}.replay", mode.replace(' ', "-"), time));
+        File::create(&path)?.write_all(self.to_text().as_bytes())?;
+        Ok(path)
+    }
+
+    pub fn load(path: &Path) -> Result<Replay, String> {
+        let mut content = String::new();
+        File::open(path)
+            .and_then(|mut f| f.read_to_string(&mut content))
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        Replay::parse(&content).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+}
+
+fn parse_inputs(line: &str) -> Result<Vec<(u32, Vec<Action>)>, String> {
+    let mut frame = 0u32;
+    line.split_whitespace()
+        .map(|entry| {
+            let split = entry
+                .find(|c: char| !c.is_ascii_digit())
+                .unwrap_or(entry.len());
+            let (delta, actions) = entry.split_at(split);
+            frame = delta
+                .parse::<u32>()
+                .ok()
+                .and_then(|delta| frame.checked_add(delta))
+                .ok_or_else(|| format!("Invalid actions: {}", entry))?;
+            let actions = actions
+                .chars()
+                .map(|c| Action::from_char(c).ok_or(format!("Invalid action: {}", c)))
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok((frame, actions))
+        })
+        .collect()
+}
+
+// Plays a replay back one frame at a time, the same way as the games were
+// played: the actions of the frame, then the gravity unless the game ended.
+pub struct Playback<'a> {
+    replay: &'a Replay,
+    players: Vec<Tetris>,
+    frame: u32,
+    finished: bool,
+    // States of the games every SNAPSHOT_INTERVAL frames.
+    snapshots: Vec<Vec<Tetris>>,
+}
+
+impl<'a> Playback<'a> {
+    pub fn new(replay: &'a Replay) -> Playback<'a> {
+        let players = replay
+            .players
+            .iter()
+            .map(|player| Tetris::with_seed(replay.rules, player.seed))
+            .collect::<Vec<_>>();
+        Playback {
+            replay,
+            snapshots: vec![players.clone()],
+            players,
+            frame: 0,
+            finished: false,
+        }
+    }
+
+    pub fn players(&self) -> &[Tetris] {
+        &self.players
+    }
+
+    pub fn frame(&self) -> u32 {
+        self.frame
+    }
+
+    pub fn last_frame(&self) -> u32 {
+        self.replay
+            .players
+            .iter()
+            .map(|player| player.outcome.frames)
+            .max()
+            .unwrap_or(0)
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    fn actions(&self, player: usize) -> &[Action] {
+        let inputs = &self.replay.players[player].inputs;
+        match inputs.binary_search_by_key(&self.frame, |(frame, _)| *frame) {
+            Ok(index) => &inputs[index].1,
+            Err(_) => &[],
+        }
+    }
+
+    pub fn step(&mut self) {
+        if self.finished {
+            return;
+        }
+        for player in 0..self.players.len() {
+            let actions = self.actions(player).to_vec();
+            apply_actions(&mut self.players[player], &actions);
+        }
+        if let [first, second] = self.players.as_mut_slice() {
+            exchange_garbage(first, second);
+        }
+        if self.frame >= self.last_frame()
+            || self
+                .players
+                .iter()
+                .any(|tetris| tetris.game_over || tetris.is_complete())
+        {
+            self.finished = true;
+            return;
+        }
+        for tetris in self.players.iter_mut() {
+            tetris.tick();
+        }
+        if let [first, second] = self.players.as_mut_slice() {
+            exchange_garbage(first, second);
+        }
+        self.frame += 1;
+        if self.frame.is_multiple_of(SNAPSHOT_INTERVAL)
+            && self.snapshots.len() as u32 == self.frame / SNAPSHOT_INTERVAL
+        {
+            self.snapshots.push(self.players.clone());
+        }
+    }
+
+    pub fn seek(&mut self, frame: u32) {
+        if frame < self.frame {
+            let index = ((frame / SNAPSHOT_INTERVAL) as usize).min(self.snapshots.len() - 1);
+            self.players = self.snapshots[index].clone();
+            self.frame = index as u32 * SNAPSHOT_INTERVAL;
+            self.finished = false;
+        }
+        while self.frame < frame && !self.finished {
+            self.step();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ai::{Ai, Weights};
+    use crate::batch;
+
+    fn rules() -> Rules {
+        Rules {
+            mode: Mode::Sprint(40),
+            gravity: Gravity::Guideline,
+            finesse: Finesse::Count,
+            preview: 3,
+            hold: true,
+            ..Rules::default()
+        }
+    }
+
+    // A game played by the AI, with a delay between its moves so that they
+    // are spread over the frames.
+    fn played(seed: u64) -> Tetris {
+        batch::play_game(&mut Ai::new(Weights::default(), 2), rules(), seed, Some(40))
+    }
+
+    // The outcomes of the players of a replay played back to its end.
+    fn play_back(replay: &Replay) -> Vec<Outcome> {
+        let mut playback = Playback::new(replay);
+        while !playback.is_finished() {
+            playback.step();
+        }
+        playback.players().iter().map(Outcome::of).collect()
+    }
+
+    #[test]
+    fn text_round_trip() {
+        let tetris = played(5);
+        let replay = Replay::record(std::slice::from_ref(&tetris)).unwrap();
+        let parsed = Replay::parse(&replay.to_text()).unwrap();
+        assert_eq!(parsed.rules, replay.rules);
+        assert_eq!(parsed.players.len(), 1);
+        assert_eq!(parsed.players[0].seed, 5);
+        assert_eq!(parsed.players[0].outcome, Outcome::of(&tetris));
+        assert!(!parsed.players[0].inputs.is_empty());
+        assert_eq!(parsed.players[0].inputs, tetris.inputs);
+    }
+
+    #[test]
+    fn recorded_game_plays_back() {
+        let tetris = played(7);
+        let replay = Replay::parse(&Replay::record(&[tetris]).unwrap().to_text()).unwrap();
+        assert_eq!(play_back(&replay), vec![replay.players[0].outcome]);
+    }
+
+    #[test]
+    fn tampered_score_is_a_mismatch() {
+        let text = Replay::record(&[played(9)]).unwrap().to_text();
+        let player = text
+            .lines()
+            .find(|line| line.starts_with("player "))
+            .unwrap();
+        let words = player.split(' ').collect::<Vec<_>>();
+        let score = words[2].parse::<u32>().unwrap() + 100;
+        let tampered = format!("player {} {} {}", words[1], score, words[3..].join(" "));
+        let replay = Replay::parse(&text.replace(player, &tampered)).unwrap();
+        assert_eq!(replay.players[0].outcome.score, score);
+        assert_ne!(play_back(&replay), vec![replay.players[0].outcome]);
+    }
+
+    #[test]
+    fn invalid_inputs() {
+        assert_eq!(
+            parse_inputs("0 2L 3RH").unwrap(),
+            vec![
+                (0, vec![]),
+                (2, vec![Action::MoveLeft]),
+                (5, vec![Action::MoveRight, Action::HardDrop])
+            ]
+        );
+        assert!(parse_inputs("4294967295 1L").is_err());
+        assert!(parse_inputs("L").is_err());
+        assert!(parse_inputs("3?").is_err());
+    }
+}
@@ -0,0 +1,218 @@
+/*
+ * This module runs the two games of a network versus match with rollback. The
+ * local games go on without waiting for the actions of the remote player,
+ * predicting that they made none, and a snapshot of both games is kept at the
+ * start of every frame not confirmed yet. When the remote actions of a frame
+ * turn out to differ from the prediction, both games are restored from the
+ * snapshot of that frame and played again up to the current one. The input
+ * logs of the games, which grow with the match, are kept out of the
+ * snapshots, so that a snapshot costs the same at any point of the match.
+ */
+use crate::engine::{apply_actions, exchange_garbage, is_over, Action, Tetris};
+use std::collections::VecDeque;
+
+type InputLog = Vec<(u32, Vec<Action>)>;
+
+// Length of an input log, and of the actions of its last frame, which a game
+// over adds the actions of the next frames to.
+type LogLength = (usize, usize);
+
+fn log_length(log: &InputLog) -> LogLength {
+    (
+        log.len(),
+        log.last().map_or(0, |(_, actions)| actions.len()),
+    )
+}
+
+fn truncate_log(log: &mut InputLog, (len, last): LogLength) {
+    log.truncate(len);
+    if let Some((_, actions)) = log.last_mut() {
+        actions.truncate(last);
+    }
+}
+
+// Moves the actions logged by a game into the log of its match.
+fn append_log(log: &mut InputLog, tetris: &mut Tetris) {
+    for (frame, actions) in tetris.inputs.drain(..) {
+        match log.last_mut() {
+            Some((last, last_actions)) if *last == frame => last_actions.extend(actions),
+            _ => log.push((frame, actions)),
+        }
+    }
+}
+
+// Plays a frame of both games: the actions of both players, then the gravity,
+// the same way as a local two player game.
+pub fn step(players: &mut [Tetris; 2], actions: [&[Action]; 2]) {
+    for (tetris, actions) in players.iter_mut().zip(actions) {
+        apply_actions(tetris, actions);
+    }
+    let [first, second] = players;
+    exchange_garbage(first, second);
+    if is_over(players) {
+        return;
+    }
+    for tetris in players.iter_mut() {
+        tetris.tick();
+    }
+    let [first, second] = players;
+    exchange_garbage(first, second);
+}
+
+pub struct Rollback {
+    me: usize,
+    // Frames the games can be played ahead of the remote actions, 0 playing
+    // in lockstep.
+    max_rollback: u32,
+    // The first frame whose actions are not all known yet.
+    confirmed: u32,
+    // Both games at the start of every frame from the confirmed one on, the
+    // last snapshot being the current state of the games, without their input
+    // logs but with the length of the logs at that frame.
+    snapshots: VecDeque<([Tetris; 2], [LogLength; 2])>,
+    // Input logs of both games up to the current frame.
+    logs: [InputLog; 2],
+    // Actions of both players for every frame from the confirmed one on, None
+    // until they are known.
+    inputs: [VecDeque<Option<Vec<Action>>>; 2],
+    // The first played frame whose remote actions differ from the prediction.
+    mispredicted: Option<u32>,
+    // Number of times the games were played again, and frames played again.
+    pub nb_rollbacks: u32,
+    pub resimulated_frames: u32,
+}
+
+impl Rollback {
+    pub fn new(mut players: [Tetris; 2], me: usize, max_rollback: u32) -> Rollback {
+        let logs = players
+            .each_mut()
+            .map(|tetris| std::mem::take(&mut tetris.inputs));
+        Rollback {
+            me,
+            max_rollback,
+            confirmed: 0,
+            snapshots: VecDeque::from([(players, logs.each_ref().map(log_length))]),
+            logs,
+            inputs: [VecDeque::new(), VecDeque::new()],
+            mispredicted: None,
+            nb_rollbacks: 0,
+            resimulated_frames: 0,
+        }
+    }
+
+    // The frame the current state of the games is at.
+    pub fn frame(&self) -> u32 {
+        self.confirmed + self.snapshots.len() as u32 - 1
+    }
+
+    pub fn confirmed_frame(&self) -> u32 {
+        self.confirmed
+    }
+
+    // The current state of the games, without their input logs.
+    pub fn current(&self) -> &[Tetris; 2] {
+        &self.snapshots.back().expect("No current state.").0
+    }
+
+    // The games as of the last frame played with the actual actions of both
+    // players, which won't change anymore, without their input logs.
+    pub fn confirmed(&self) -> &[Tetris; 2] {
+        &self.snapshots.front().expect("No confirmed state.").0
+    }
+
+    // The confirmed games along with their input logs, to be recorded.
+    pub fn confirmed_games(&self) -> [Tetris; 2] {
+        let (players, lengths) = self.snapshots.front().expect("No confirmed state.");
+        let mut players = players.clone();
+        for ((tetris, log), length) in players.iter_mut().zip(&self.logs).zip(lengths) {
+            tetris.inputs = log.clone();
+            truncate_log(&mut tetris.inputs, *length);
+        }
+        players
+    }
+
+    // Records the actions of a player for a frame. Actions of frames already
+    // confirmed are ignored.
+    pub fn set_input(&mut self, player: usize, frame: u32, actions: Vec<Action>) {
+        if frame < self.confirmed {
+            return;
+        }
+        if player != self.me && frame < self.frame() && !actions.is_empty() {
+            self.mispredicted = Some(self.mispredicted.map_or(frame, |f| f.min(frame)));
+        }
+        let index = (frame - self.confirmed) as usize;
+        let inputs = &mut self.inputs[player];
+        while inputs.len() <= index {
+            inputs.push_back(None);
+        }
+        inputs[index] = Some(actions);
+    }
+
+    fn input(&self, player: usize, frame: u32) -> Option<&[Action]> {
+        self.inputs[player]
+            .get((frame - self.confirmed) as usize)
+            .and_then(|actions| actions.as_deref())
+    }
+
+    // Plays the current frame if the local actions for it are known and the
+    // games are not too far ahead of the remote actions.
+    fn advance(&mut self) -> bool {
+        let frame = self.frame();
+        let local = match self.input(self.me, frame) {
+            Some(local) => local,
+            None => return false,
+        };
+        let remote = self.input(1 - self.me, frame);
+        if remote.is_none() && frame >= self.confirmed + self.max_rollback {
+            return false;
+        }
+        let mut actions = [local, remote.unwrap_or(&[])];
+        if self.me == 1 {
+            actions.swap(0, 1);
+        }
+        let mut players = self.current().clone();
+        step(&mut players, actions);
+        for (log, tetris) in self.logs.iter_mut().zip(players.iter_mut()) {
+            append_log(log, tetris);
+        }
+        let lengths = self.logs.each_ref().map(log_length);
+        self.snapshots.push_back((players, lengths));
+        true
+    }
+
+    // Drops the snapshots and actions of the frames now known for sure.
+    fn confirm(&mut self) {
+        while self.snapshots.len() > 1
+            && self
+                .inputs
+                .iter()
+                .all(|inputs| matches!(inputs.front(), Some(Some(_))))
+        {
+            self.snapshots.pop_front();
+            for inputs in self.inputs.iter_mut() {
+                inputs.pop_front();
+            }
+            self.confirmed += 1;
+        }
+    }
+
+    // Plays the games again from a mispredicted frame if needed, then as far
+    // as the known actions allow.
+    pub fn update(&mut self) {
+        if let Some(frame) = self.mispredicted.take() {
+            let current = self.frame();
+            self.snapshots
+                .truncate((frame - self.confirmed) as usize + 1);
+            let lengths = self.snapshots.back().expect("No current state.").1;
+            for (log, length) in self.logs.iter_mut().zip(lengths) {
+                truncate_log(log, length);
+            }
+            while self.frame() < current && self.advance() {
+                self.resimulated_frames += 1;
+            }
+            self.nb_rollbacks += 1;
+        }
+        while self.advance() {}
+        self.confirm();
+    }
+}
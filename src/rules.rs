@@ -0,0 +1,342 @@
+/*
+ * This module contains the rules that can be tuned from the command line:
+ * the game mode, the gravity curve giving the time a piece takes to fall by
+ * one row at a given level, and the rule deciding when the level goes up.
+ * Levels start at 1 and are unbounded.
+ */
+use crate::puzzle::Goal;
+
+const LEVEL_TIMES: [u32; 10] = [1000, 850, 700, 600, 500, 400, 300, 250, 221, 190];
+const LEVEL_LINES: [u32; 10] = [20, 40, 60, 80, 100, 120, 140, 160, 180, 200];
+// Frames per row on the NES, starting from its level 0.
+const NES_FRAMES: [u32; 29] = [
+    48, 43, 38, 33, 28, 23, 18, 13, 8, 6, 5, 5, 5, 4, 4, 4, 3, 3, 3, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2,
+];
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Mode {
+    // The game goes on until the stack reaches the top.
+    Endless,
+    // The game is won once the given number of lines has been cleared.
+    Marathon(u32),
+    // A timed race to clear the given number of lines.
+    Sprint(u32),
+    // A race for the best score in the given number of minutes.
+    Ultra(u32),
+    // A timed race to clear the given number of garbage lines.
+    Dig(u32),
+    // Section levels from 0 to 999 with a gravity reaching 20G, graded at the end.
+    Master,
+    // A given board and piece queue to reach the goal with.
+    Puzzle(Goal),
+    // Two players side by side, lines cleared by one rising as garbage on the
+    // other's board, until one of them reaches the top.
+    Versus,
+    // Two players side by side playing for a shared score, until one of them
+    // reaches the top.
+    Coop,
+}
+
+impl Mode {
+    // The mode as written in replays, "sprint 40" for instance. Puzzles have
+    // none, their board and queue not being part of the rules.
+    pub fn to_words(self) -> Option<String> {
+        match self {
+            Mode::Endless => Some("endless".to_string()),
+            Mode::Marathon(lines) => Some(format!("marathon {}", lines)),
+            Mode::Sprint(lines) => Some(format!("sprint {}", lines)),
+            Mode::Ultra(minutes) => Some(format!("ultra {}", minutes)),
+            Mode::Dig(lines) => Some(format!("dig {}", lines)),
+            Mode::Master => Some("master".to_string()),
+            Mode::Puzzle(_) => None,
+            Mode::Versus => Some("versus".to_string()),
+            Mode::Coop => Some("coop".to_string()),
+        }
+    }
+
+    pub fn from_words(words: &[&str]) -> Option<Mode> {
+        let goal = |goal: &str| goal.parse::<u32>().ok();
+        match words {
+            ["endless"] => Some(Mode::Endless),
+            ["marathon", lines] => goal(lines).map(Mode::Marathon),
+            ["sprint", lines] => goal(lines).map(Mode::Sprint),
+            ["ultra", minutes] => goal(minutes).map(Mode::Ultra),
+            ["dig", lines] => goal(lines).map(Mode::Dig),
+            ["master"] => Some(Mode::Master),
+            ["versus"] => Some(Mode::Versus),
+            ["coop"] => Some(Mode::Coop),
+            _ => None,
+        }
+    }
+
+    pub fn players(self) -> usize {
+        match self {
+            Mode::Versus | Mode::Coop => 2,
+            _ => 1,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Gravity {
+    // The original table, staying at its fastest speed past level 10.
+    Classic,
+    // The Tetris guideline formula: (0.8 - (level - 1) * 0.007) ^ (level - 1) seconds per row.
+    Guideline,
+    // The NES frame table, one frame per row from its level 29 on.
+    Nes,
+}
+
+impl Gravity {
+    pub fn from_name(name: &str) -> Option<Gravity> {
+        match name {
+            "classic" => Some(Gravity::Classic),
+            "guideline" => Some(Gravity::Guideline),
+            "nes" => Some(Gravity::Nes),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Gravity::Classic => "classic",
+            Gravity::Guideline => "guideline",
+            Gravity::Nes => "nes",
+        }
+    }
+
+    // Milliseconds a piece takes to fall by one row at the given level.
+    pub fn level_time(self, level: u32) -> u32 {
+        let index = level.max(1) as usize - 1;
+        match self {
+            Gravity::Classic => LEVEL_TIMES[index.min(LEVEL_TIMES.len() - 1)],
+            Gravity::Guideline => {
+                // Past level 20 the formula is well under a millisecond anyway,
+                // capping the level keeps its base positive.
+                let n = index.min(100) as f64;
+                let seconds = (0.8 - n * 0.007).powf(n);
+                (seconds * 1000.).round().max(1.) as u32
+            }
+            Gravity::Nes => NES_FRAMES.get(index).copied().unwrap_or(1) * 1000 / 60,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum LevelUp {
+    // The original table: 20 more lines per level, counted from the start of the game.
+    Table,
+    // A new level every 10 lines.
+    Fixed,
+    // The guideline variable goal: level N is left after clearing 5 * N lines in it.
+    Variable,
+}
+
+impl LevelUp {
+    pub fn from_name(name: &str) -> Option<LevelUp> {
+        match name {
+            "table" => Some(LevelUp::Table),
+            "fixed" => Some(LevelUp::Fixed),
+            "variable" => Some(LevelUp::Variable),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            LevelUp::Table => "table",
+            LevelUp::Fixed => "fixed",
+            LevelUp::Variable => "variable",
+        }
+    }
+
+    // `nb_lines` is the total number of lines cleared and `level_lines` the
+    // number of lines cleared since the current level was reached.
+    pub fn is_level_up(self, level: u32, nb_lines: u32, level_lines: u32) -> bool {
+        match self {
+            LevelUp::Table => {
+                let index = level.max(1) as usize - 1;
+                let last = LEVEL_LINES.len() - 1;
+                let goal = match LEVEL_LINES.get(index) {
+                    Some(goal) => *goal,
+                    None => LEVEL_LINES[last] + 20 * (index - last) as u32,
+                };
+                nb_lines > goal
+            }
+            LevelUp::Fixed => level_lines >= 10,
+            LevelUp::Variable => level_lines >= 5 * level,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Finesse {
+    // Pieces aren't checked, as for bots, the check costing as much as a move
+    // of the built-in AI.
+    Off,
+    // Pieces placed with more moves than needed count as finesse faults.
+    Count,
+    // Such pieces also start again from the top instead of locking.
+    Training,
+}
+
+impl Finesse {
+    pub fn from_name(name: &str) -> Option<Finesse> {
+        match name {
+            "off" => Some(Finesse::Off),
+            "count" => Some(Finesse::Count),
+            "training" => Some(Finesse::Training),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Finesse::Off => "off",
+            Finesse::Count => "count",
+            Finesse::Training => "training",
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Rules {
+    pub mode: Mode,
+    // Chance in percent of a garbage hole changing column between two rows.
+    pub messiness: u32,
+    pub start_level: u32,
+    pub gravity: Gravity,
+    pub level_up: LevelUp,
+    // What is done with the pieces placed with more moves than needed.
+    pub finesse: Finesse,
+    // Number of pieces to come shown, up to engine::PREVIEW_PIECES, and
+    // whether the current piece can be put aside. The classic game has none.
+    pub preview: usize,
+    pub hold: bool,
+}
+
+impl Default for Rules {
+    fn default() -> Rules {
+        Rules {
+            mode: Mode::Endless,
+            messiness: 30,
+            start_level: 1,
+            gravity: Gravity::Classic,
+            level_up: LevelUp::Table,
+            finesse: Finesse::Off,
+            preview: 0,
+            hold: false,
+        }
+    }
+}
+
+// Internal gravity of the master mode in 1/256th of a row per frame, from the
+// section level at which it applies. 5120 is 20G: pieces land as they spawn.
+const MASTER_GRAVITY: [(u32, u32); 30] = [
+    (0, 4),
+    (30, 6),
+    (35, 8),
+    (40, 10),
+    (50, 12),
+    (60, 16),
+    (70, 32),
+    (80, 48),
+    (90, 64),
+    (100, 80),
+    (120, 96),
+    (140, 112),
+    (160, 128),
+    (170, 144),
+    (200, 4),
+    (220, 32),
+    (230, 64),
+    (233, 96),
+    (236, 128),
+    (239, 160),
+    (243, 192),
+    (247, 224),
+    (251, 256),
+    (300, 512),
+    (330, 768),
+    (360, 1024),
+    (400, 1280),
+    (420, 1024),
+    (450, 768),
+    (500, 5120),
+];
+pub const MASTER_LAST_LEVEL: u32 = 999;
+// Score needed for every grade of the master mode, from 9 to S9.
+const MASTER_GRADES: [(&str, u32); 18] = [
+    ("9", 0),
+    ("8", 400),
+    ("7", 800),
+    ("6", 1400),
+    ("5", 2000),
+    ("4", 3500),
+    ("3", 5500),
+    ("2", 8000),
+    ("1", 12000),
+    ("S1", 16000),
+    ("S2", 22000),
+    ("S3", 30000),
+    ("S4", 40000),
+    ("S5", 52000),
+    ("S6", 66000),
+    ("S7", 82000),
+    ("S8", 100000),
+    ("S9", 120000),
+];
+
+pub fn master_gravity(level: u32) -> u32 {
+    MASTER_GRAVITY
+        .iter()
+        .rev()
+        .find(|(from, _)| level >= *from)
+        .map(|(_, gravity)| *gravity)
+        .unwrap_or(4)
+}
+
+// Delays of the master mode in frames, getting shorter in the last sections.
+pub struct Timings {
+    // Before the next piece spawns, after a piece locked without clearing lines
+    // or after the lines it cleared disappeared.
+    pub are: u32,
+    pub line_are: u32,
+    // Time a landed piece can still be moved before it locks.
+    pub lock: u32,
+    pub line_clear: u32,
+}
+
+pub fn master_timings(level: u32) -> Timings {
+    let (are, line_are, lock, line_clear) = match level {
+        0..=499 => (25, 25, 30, 40),
+        500..=599 => (25, 25, 30, 25),
+        600..=699 => (25, 16, 30, 16),
+        700..=799 => (16, 12, 30, 12),
+        800..=899 => (12, 6, 30, 6),
+        _ => (12, 6, 17, 6),
+    };
+    Timings {
+        are,
+        line_are,
+        lock,
+        line_clear,
+    }
+}
+
+pub fn master_grade(score: u32) -> &'static str {
+    MASTER_GRADES
+        .iter()
+        .rev()
+        .find(|(_, needed)| score >= *needed)
+        .map(|(grade, _)| *grade)
+        .unwrap_or("9")
+}
+
+// The grandmaster grade needs an S9 score at level 999 and to have been fast
+// and good enough at levels 300 and 500: (level, score, seconds) requirements.
+pub const GRANDMASTER_CHECKS: [(u32, u32, u32); 3] = [
+    (300, 12000, 4 * 60 + 15),
+    (500, 40000, 7 * 60 + 30),
+    (999, 126000, 13 * 60 + 30),
+];
@@ -0,0 +1,534 @@
+/*
+ * This module lets an external bot play a game through the Tetris Bot
+ * Protocol, as spoken by engines like Cold Clear: the bot is a process of its
+ * own, and messages are JSON objects sent one per line over its standard
+ * input and output, their "type" field telling them apart.
+ *
+ *     bot        info {name, version, author, features}
+ *     frontend   rules {}
+ *     bot        ready | error {reason}
+ *     frontend   start {hold, queue, combo, back_to_back, board}
+ *     frontend   suggest
+ *     bot        suggestion {moves}
+ *     frontend   play {move}
+ *     frontend   new_piece {piece}
+ *     frontend   stop | quit
+ *
+ * The queue of "start" begins with the current piece, and the board is 40
+ * rows of 10 cells from the bottom one up, a cell being null or the letter of
+ * a piece, "G" for garbage. Moves give where a piece locks, as the center of
+ * the piece and its orientation in SRS, the guideline rotation system, and
+ * whether it is spun in.
+ *
+ * The rotations of the game aren't the ones of SRS, so a suggestion is played
+ * by finding a move of the piece, or of the held one, covering the same cells
+ * as it. The bot keeps track of the game from the moves played and the pieces
+ * added to the queue; when the game doesn't go the way it expects, as when
+ * garbage rises or a suggestion can't be played, it is stopped and started
+ * again from the game as it is. Suggestions which can't be played are
+ * replaced by a move of the built-in AI.
+ */
+use crate::ai::{self, Weights};
+use crate::board::Board;
+use crate::bot::{Agent, Plan};
+use crate::engine::{Action, Tetrimino, TetriminoTypes, Tetris, GARBAGE};
+use crate::movegen;
+use serde::{Deserialize, Serialize};
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, TryRecvError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const BOARD_ROWS: usize = 40;
+// Time the bot has to introduce itself and accept the rules, to answer in a
+// headless game unless told otherwise, and to quit once told to.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+pub const SUGGESTION_TIMEOUT: Duration = Duration::from_secs(10);
+const QUIT_TIMEOUT: Duration = Duration::from_secs(1);
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Orientation {
+    North,
+    East,
+    South,
+    West,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Spin {
+    None,
+    Mini,
+    Full,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub struct PieceLocation {
+    #[serde(rename = "type")]
+    pub piece: char,
+    pub orientation: Orientation,
+    // Center of the piece, from the bottom left corner of the board.
+    pub x: i32,
+    pub y: i32,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub struct TbpMove {
+    pub location: PieceLocation,
+    pub spin: Spin,
+}
+
+#[derive(Serialize, Clone, PartialEq, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum FrontendMessage {
+    Rules {},
+    Start {
+        hold: Option<char>,
+        queue: Vec<char>,
+        combo: u32,
+        back_to_back: bool,
+        board: Vec<Vec<Option<char>>>,
+    },
+    Suggest,
+    Play {
+        #[serde(rename = "move")]
+        played: TbpMove,
+    },
+    NewPiece {
+        piece: char,
+    },
+    Stop,
+    Quit,
+}
+
+#[derive(Deserialize, Clone, PartialEq, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum BotMessage {
+    Info {
+        #[serde(default)]
+        name: String,
+        #[serde(default)]
+        version: String,
+        #[serde(default)]
+        author: String,
+        #[serde(default)]
+        features: Vec<String>,
+    },
+    Ready,
+    Error {
+        #[serde(default)]
+        reason: String,
+    },
+    Suggestion {
+        moves: Vec<TbpMove>,
+    },
+    // Messages of later versions of the protocol, which are ignored.
+    #[serde(other)]
+    Unknown,
+}
+
+// Cells of a piece facing north, around its center, y going up.
+fn north_cells(piece: TetriminoTypes) -> [(i32, i32); 4] {
+    match piece {
+        TetriminoTypes::TetriminoI => [(-1, 0), (0, 0), (1, 0), (2, 0)],
+        TetriminoTypes::TetriminoO => [(0, 0), (1, 0), (0, 1), (1, 1)],
+        TetriminoTypes::TetriminoT => [(-1, 0), (0, 0), (1, 0), (0, 1)],
+        TetriminoTypes::TetriminoL => [(-1, 0), (0, 0), (1, 0), (1, 1)],
+        TetriminoTypes::TetriminoJ => [(-1, 0), (0, 0), (1, 0), (-1, 1)],
+        TetriminoTypes::TetriminoS => [(-1, 0), (0, 0), (0, 1), (1, 1)],
+        TetriminoTypes::TetriminoZ => [(-1, 1), (0, 1), (0, 0), (1, 0)],
+    }
+}
+
+// Cells covered by a piece at a location, sorted, as in cells.
+pub fn location_cells(location: &PieceLocation) -> Option<Vec<(i32, i32)>> {
+    let piece = TetriminoTypes::from_char(location.piece)?;
+    let mut cells = north_cells(piece)
+        .iter()
+        .map(|&(x, y)| match location.orientation {
+            Orientation::North => (x, y),
+            Orientation::East => (y, -x),
+            Orientation::South => (-x, -y),
+            Orientation::West => (-y, x),
+        })
+        .map(|(x, y)| (location.x + x, location.y + y))
+        .collect::<Vec<_>>();
+    cells.sort();
+    Some(cells)
+}
+
+// Cells covered by a piece of the game, sorted, from the bottom left corner
+// of the map up as in the protocol.
+pub fn cells(game_map: &Board, piece: &Tetrimino) -> Vec<(i32, i32)> {
+    let mut cells = piece
+        .cells()
+        .iter()
+        .map(|(x, y)| (*x as i32, game_map.height() as i32 - 1 - *y as i32))
+        .collect::<Vec<_>>();
+    cells.sort();
+    cells
+}
+
+fn cell_char(case: u8) -> Option<char> {
+    const PIECES: [char; 7] = ['I', 'J', 'L', 'O', 'S', 'T', 'Z'];
+    match case {
+        0 => None,
+        GARBAGE => Some('G'),
+        _ => PIECES.get(case as usize - 1).copied(),
+    }
+}
+
+// The map of the game as a board of the protocol, with empty rows above it.
+pub fn board(game_map: &Board) -> Vec<Vec<Option<char>>> {
+    let colors = game_map.color_rows();
+    (0..BOARD_ROWS)
+        .map(|y| match colors.len().checked_sub(y + 1) {
+            Some(row) => colors[row].iter().map(|case| cell_char(*case)).collect(),
+            None => vec![None; game_map.width()],
+        })
+        .collect()
+}
+
+// Pieces drawn since the start of the game.
+fn drawn_pieces(tetris: &Tetris) -> u32 {
+    tetris.nb_pieces
+        + tetris.queue.len() as u32
+        + tetris.hold_piece.is_some() as u32
+        + tetris.current_piece.is_some() as u32
+}
+
+// What the bot makes of the game once the move it was told about is played.
+struct Expected {
+    game_map: Board,
+    hold_piece: Option<TetriminoTypes>,
+}
+
+pub struct TbpBot {
+    pub name: String,
+    pub version: String,
+    pub author: String,
+    // Moves of the built-in AI played instead of the ones of the bot.
+    pub fallbacks: u32,
+    child: Child,
+    stdin: ChildStdin,
+    messages: Receiver<BotMessage>,
+    // Whether the game waits for the suggestions, as a headless one, instead
+    // of going on while the bot thinks, and for how long at most.
+    blocking: bool,
+    pub suggestion_timeout: Option<Duration>,
+    started: bool,
+    dead: bool,
+    expected: Option<Expected>,
+    // Pieces drawn the bot knows about.
+    known_pieces: u32,
+    // The piece suggestions were asked for, as its number and the finesse
+    // faults of the game, a piece starting again from the top in finesse
+    // training, and whether the bot is still thinking about it.
+    piece: Option<(u32, u32)>,
+    waiting: bool,
+    plan: Plan,
+}
+
+impl TbpBot {
+    // Launches the bot with its arguments and waits for it to accept the rules.
+    pub fn launch(command: &[String], move_delay: u32, blocking: bool) -> Result<TbpBot, String> {
+        let (program, args) = command
+            .split_first()
+            .ok_or_else(|| "No bot command given.".to_string())?;
+        let mut child = Command::new(program)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(|e| format!("Failed to launch {}: {}", program, e))?;
+        let (stdin, stdout) = match (child.stdin.take(), child.stdout.take()) {
+            (Some(stdin), Some(stdout)) => (stdin, stdout),
+            _ => return Err(format!("Failed to talk to {}", program)),
+        };
+
+        // The messages are read on a thread of their own, so that the game
+        // can go on while the bot thinks.
+        let (sender, messages) = mpsc::channel();
+        thread::spawn(move || {
+            for line in BufReader::new(stdout).lines() {
+                let line = match line {
+                    Ok(line) => line,
+                    Err(_) => break,
+                };
+                if line.trim().is_empty() {
+                    continue;
+                }
+                match serde_json::from_str::<BotMessage>(&line) {
+                    Ok(message) => {
+                        if sender.send(message).is_err() {
+                            break;
+                        }
+                    }
+                    Err(e) => eprintln!("Invalid message from the bot: {}: {}", e, line),
+                }
+            }
+        });
+
+        let mut bot = TbpBot {
+            name: String::new(),
+            version: String::new(),
+            author: String::new(),
+            fallbacks: 0,
+            child,
+            stdin,
+            messages,
+            blocking,
+            suggestion_timeout: Some(SUGGESTION_TIMEOUT),
+            started: false,
+            dead: false,
+            expected: None,
+            known_pieces: 0,
+            piece: None,
+            waiting: false,
+            plan: Plan::new(move_delay),
+        };
+        match bot.receive(Some(HANDSHAKE_TIMEOUT))? {
+            BotMessage::Info {
+                name,
+                version,
+                author,
+                ..
+            } => {
+                bot.name = name;
+                bot.version = version;
+                bot.author = author;
+            }
+            message => return Err(format!("Expected info from the bot, got {:?}", message)),
+        }
+        bot.send(&FrontendMessage::Rules {});
+        match bot.receive(Some(HANDSHAKE_TIMEOUT))? {
+            BotMessage::Ready => Ok(bot),
+            BotMessage::Error { reason } => Err(format!("The bot refused the rules: {}", reason)),
+            message => Err(format!("Expected ready from the bot, got {:?}", message)),
+        }
+    }
+
+    // The next message of the bot, skipping the unknown ones, waiting for it
+    // without a timeout for as long as it takes.
+    fn receive(&mut self, timeout: Option<Duration>) -> Result<BotMessage, String> {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        loop {
+            let message = match deadline {
+                Some(deadline) => self
+                    .messages
+                    .recv_timeout(deadline.saturating_duration_since(Instant::now())),
+                None => self
+                    .messages
+                    .recv()
+                    .map_err(|_| RecvTimeoutError::Disconnected),
+            };
+            match message {
+                Ok(BotMessage::Unknown) => continue,
+                Ok(message) => return Ok(message),
+                Err(RecvTimeoutError::Timeout) => {
+                    return Err("The bot took too long to answer.".to_string())
+                }
+                Err(RecvTimeoutError::Disconnected) => return Err("The bot quit.".to_string()),
+            }
+        }
+    }
+
+    fn send(&mut self, message: &FrontendMessage) {
+        if self.dead {
+            return;
+        }
+        let line = match serde_json::to_string(message) {
+            Ok(line) => line,
+            Err(_) => return,
+        };
+        if writeln!(self.stdin, "{}", line)
+            .and_then(|_| self.stdin.flush())
+            .is_err()
+        {
+            self.give_up("The bot quit.");
+        }
+    }
+
+    // Stops talking to the bot, the built-in AI playing in its place.
+    fn give_up(&mut self, reason: &str) {
+        if !self.dead {
+            eprintln!("{} The built-in AI plays in its place.", reason);
+        }
+        self.dead = true;
+    }
+
+    // Tells the bot about the pieces drawn since the last move, or starts it
+    // again from the game as it is when it doesn't expect it, then asks for
+    // suggestions for the current piece.
+    fn request(&mut self, tetris: &Tetris) {
+        let in_sync = matches!(self.expected, Some(ref expected)
+            if expected.game_map == tetris.game_map && expected.hold_piece == tetris.hold_piece);
+        let drawn = drawn_pieces(tetris);
+        if in_sync {
+            // The pieces drawn since are the last ones the bot is to know of,
+            // the current piece itself without a preview.
+            let known = tetris
+                .last_piece
+                .into_iter()
+                .chain(tetris.queue.iter().copied())
+                .collect::<Vec<_>>();
+            let new_pieces = (drawn.saturating_sub(self.known_pieces) as usize).min(known.len());
+            for piece in &known[known.len() - new_pieces..] {
+                self.send(&FrontendMessage::NewPiece {
+                    piece: piece.to_char(),
+                });
+            }
+        } else {
+            if self.started {
+                self.send(&FrontendMessage::Stop);
+            }
+            self.send(&FrontendMessage::Start {
+                hold: tetris.hold_piece.map(TetriminoTypes::to_char),
+                queue: tetris
+                    .last_piece
+                    .into_iter()
+                    .chain(tetris.queue.iter().copied())
+                    .map(TetriminoTypes::to_char)
+                    .collect(),
+                combo: tetris.attack.combo,
+                back_to_back: tetris.attack.back_to_back,
+                board: board(&tetris.game_map),
+            });
+            self.started = true;
+        }
+        self.known_pieces = drawn;
+        self.expected = None;
+        self.send(&FrontendMessage::Suggest);
+        self.waiting = true;
+    }
+
+    // The moves suggested by the bot, None while it is still thinking. A bot
+    // which failed suggests nothing.
+    fn poll_suggestion(&mut self) -> Option<Vec<TbpMove>> {
+        loop {
+            if self.dead {
+                return Some(Vec::new());
+            }
+            let message = if self.blocking {
+                self.receive(self.suggestion_timeout)
+            } else {
+                match self.messages.try_recv() {
+                    Ok(message) => Ok(message),
+                    Err(TryRecvError::Empty) => return None,
+                    Err(TryRecvError::Disconnected) => Err("The bot quit.".to_string()),
+                }
+            };
+            match message {
+                Ok(BotMessage::Suggestion { moves }) => return Some(moves),
+                Ok(BotMessage::Error { reason }) => {
+                    self.give_up(&format!("The bot failed: {}.", reason))
+                }
+                Ok(_) => {}
+                Err(e) => self.give_up(&e),
+            }
+        }
+    }
+
+    // The actions playing the first suggestion the game allows, telling the
+    // bot about it, or the move of the built-in AI if there is none.
+    fn play_suggestion(&mut self, tetris: &Tetris, moves: &[TbpMove]) -> Vec<Action> {
+        for suggestion in moves {
+            if let Some((actions, hold, piece)) = find_move(tetris, suggestion) {
+                self.send(&FrontendMessage::Play {
+                    played: *suggestion,
+                });
+                self.expected = Some(Expected {
+                    game_map: ai::lock_piece(&tetris.game_map, &piece).0,
+                    hold_piece: if hold {
+                        tetris.last_piece
+                    } else {
+                        tetris.hold_piece
+                    },
+                });
+                return actions;
+            }
+        }
+        self.fallbacks += 1;
+        ai::move_actions(ai::best_move(tetris, &Weights::default()))
+    }
+}
+
+// The actions locking the current piece, or the held one, where a suggestion
+// puts it, whether it holds, and the piece where it locks. Spins are played
+// as suggested when the game allows it, with the fewest actions.
+fn find_move(tetris: &Tetris, suggestion: &TbpMove) -> Option<(Vec<Action>, bool, Tetrimino)> {
+    let target = location_cells(&suggestion.location)?;
+    let kind = TetriminoTypes::from_char(suggestion.location.piece)?;
+    let spin = suggestion.spin != Spin::None;
+    let current = tetris.current_piece?;
+    let held = tetris
+        .can_hold()
+        .then(|| tetris.hold_piece.or_else(|| tetris.queue.front().copied()))
+        .flatten();
+    [
+        (false, tetris.last_piece, Some(current)),
+        (true, held, held.map(TetriminoTypes::generate)),
+    ]
+    .into_iter()
+    .filter_map(|(hold, piece_kind, piece)| (piece_kind == Some(kind)).then_some((hold, piece?)))
+    .flat_map(|(hold, piece)| {
+        movegen::reachable_moves(&tetris.game_map, &piece)
+            .into_iter()
+            .map(move |m| (hold, m.piece(&piece), m))
+    })
+    .filter(|(_, piece, _)| cells(&tetris.game_map, piece) == target)
+    .min_by_key(|(hold, _, m)| (m.spin != spin, *hold, m.actions.len()))
+    .map(|(hold, piece, m)| {
+        let mut actions = m.actions;
+        if hold {
+            actions.insert(0, Action::Hold);
+        }
+        (actions, hold, piece)
+    })
+}
+
+impl Agent for TbpBot {
+    fn actions(&mut self, tetris: &Tetris) -> Vec<Action> {
+        if tetris.current_piece.is_none() {
+            return Vec::new();
+        }
+        let piece = (tetris.nb_pieces, tetris.finesse_faults);
+        if self.piece != Some(piece) {
+            // A piece locked before its suggestion arrived leaves the bot
+            // behind, and it is started again.
+            if self.waiting {
+                self.expected = None;
+            }
+            self.piece = Some(piece);
+            self.plan.set([], tetris.frames);
+            self.request(tetris);
+        }
+        if self.waiting {
+            let moves = match self.poll_suggestion() {
+                Some(moves) => moves,
+                None => return Vec::new(),
+            };
+            self.waiting = false;
+            let actions = self.play_suggestion(tetris, &moves);
+            self.plan.set(actions, tetris.frames);
+        }
+        self.plan.next(tetris)
+    }
+}
+
+impl Drop for TbpBot {
+    fn drop(&mut self) {
+        self.send(&FrontendMessage::Quit);
+        let deadline = Instant::now() + QUIT_TIMEOUT;
+        while Instant::now() < deadline {
+            if let Ok(Some(_)) = self.child.try_wait() {
+                return;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}